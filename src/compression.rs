@@ -0,0 +1,427 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use flate2::Compression as CompressionLevel;
+
+use crate::http::headers::HeaderMap;
+use crate::http::response::{Body, Content};
+
+/// Bodies shorter than this aren't worth the CPU time or the framing overhead.
+pub const DEFAULT_MIN_SIZE: u64 = 256;
+/// Media types that are compressed already, so compressing them again only costs time,
+/// and arbitrary binaries, which rarely compress well either.
+pub const DEFAULT_DENIED_TYPES: [&str; 16] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "audio/*",
+    "video/*",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/zstd",
+    "application/x-7z-compressed",
+    "application/x-xz",
+    "application/x-bzip2",
+    "application/octet-stream",
+];
+
+/// Buffer size the brotli encoder works in.
+const BROTLI_BUFFER_SIZE: usize = 4096;
+/// Base-2 logarithm of the brotli window, the size the reference encoder uses.
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+/// The content codings the server can apply, in the order it prefers them when
+/// a client weighs several the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Brotli,
+    Zstd,
+    Gzip,
+    Deflate,
+}
+
+impl ContentCoding {
+    pub const ALL: [ContentCoding; 4] = [Self::Brotli, Self::Zstd, Self::Gzip, Self::Deflate];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// The highest level the coding accepts; higher ones are clamped to it.
+    pub fn get_max_level(&self) -> u32 {
+        match self {
+            Self::Brotli => 11,
+            Self::Zstd => 22,
+            Self::Gzip | Self::Deflate => 9,
+        }
+    }
+
+    /// A level trading ratio for speed the way a server answering on the fly should:
+    /// brotli's and zstd's maximums are far too slow for that.
+    pub fn get_default_level(&self) -> u32 {
+        match self {
+            Self::Brotli => 4,
+            Self::Zstd => 3,
+            Self::Gzip | Self::Deflate => 6,
+        }
+    }
+}
+
+impl fmt::Display for ContentCoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ContentCoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "br" => Ok(Self::Brotli),
+            "zstd" => Ok(Self::Zstd),
+            "gzip" | "x-gzip" => Ok(Self::Gzip),
+            "deflate" => Ok(Self::Deflate),
+            _ => Err(format!("unsupported content coding: {}", s)),
+        }
+    }
+}
+
+/// Which responses get compressed, and how hard.
+///
+/// Media types are given as `type/subtype`, `type/*` or `*/*`. A body is compressed
+/// when its type matches `allowed_types`, or that list is empty, and doesn't match
+/// `denied_types`.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    pub min_size: u64,
+    pub allowed_types: Vec<String>,
+    pub denied_types: Vec<String>,
+    /// Levels for media types, the first match winning. Other types get the coding's
+    /// default level.
+    pub levels: Vec<(String, u32)>,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            min_size: DEFAULT_MIN_SIZE,
+            allowed_types: Vec::new(),
+            denied_types: DEFAULT_DENIED_TYPES.map(str::to_owned).to_vec(),
+            levels: Vec::new(),
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// `len` is `None` for a stream, which is always long enough.
+    pub fn should_compress(&self, content_type: &str, len: Option<u64>) -> bool {
+        let matches = |pattern: &String| matches_media_type(pattern, content_type);
+        len.map_or(true, |len| len >= self.min_size)
+            && (self.allowed_types.is_empty() || self.allowed_types.iter().any(matches))
+            && !self.denied_types.iter().any(matches)
+    }
+
+    pub fn get_level(&self, content_type: &str, coding: ContentCoding) -> u32 {
+        self.levels
+            .iter()
+            .find(|(pattern, _)| matches_media_type(pattern, content_type))
+            .map_or(coding.get_default_level(), |(_, level)| {
+                (*level).min(coding.get_max_level())
+            })
+    }
+}
+
+/// Whether `media_type`, parameters aside, matches `pattern`.
+pub fn matches_media_type(pattern: &str, media_type: &str) -> bool {
+    let essence = media_type.split(';').next().unwrap_or_default().trim();
+    let (type_, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+    let (pattern_type, pattern_subtype) = pattern.split_once('/').unwrap_or((pattern, ""));
+    (pattern_type == "*" || pattern_type.eq_ignore_ascii_case(type_))
+        && (pattern_subtype == "*" || pattern_subtype.eq_ignore_ascii_case(subtype))
+}
+
+/// What to do with a response body for a given `Accept-Encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Negotiation {
+    Encode(ContentCoding),
+    Identity,
+    /// Every coding the server has was refused, `identity` included.
+    NotAcceptable,
+}
+
+/// The codings listed in `Accept-Encoding` with their weights, in thousandths.
+#[derive(Debug, Clone, Default)]
+pub struct AcceptEncoding {
+    preferences: Vec<(String, u16)>,
+}
+
+impl AcceptEncoding {
+    /// Returns `None` without an `Accept-Encoding` header, which leaves the choice
+    /// to the server. Malformed elements are skipped.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        if !headers.contains("Accept-Encoding") {
+            return None;
+        }
+        let preferences = headers
+            .get_list("Accept-Encoding")
+            .into_iter()
+            .filter_map(|element| {
+                let mut params = element.split(';').map(str::trim);
+                let coding = params.next().filter(|coding| !coding.is_empty())?;
+                let weight = match params.find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("q")
+                        .then_some(value.trim())
+                }) {
+                    Some(value) => parse_qvalue(value)?,
+                    None => 1000,
+                };
+                Some((coding.to_ascii_lowercase(), weight))
+            })
+            .collect();
+        Some(Self { preferences })
+    }
+
+    /// The weight given to `coding`, either by name or through `*`.
+    fn get_weight(&self, coding: &str) -> Option<u16> {
+        let find = |name: &str| {
+            self.preferences
+                .iter()
+                .find(|(listed, _)| listed == name)
+                .map(|(_, weight)| *weight)
+        };
+        find(coding).or_else(|| find("*"))
+    }
+
+    /// Whether an unencoded body is acceptable: unless refused explicitly or through
+    /// `*;q=0`, it is.
+    pub fn accepts_identity(&self) -> bool {
+        self.get_weight("identity") != Some(0)
+    }
+
+    /// Picks the heaviest of `supported`, ties going to the earlier one. Identity is
+    /// acceptable unless refused explicitly or through `*;q=0`, but only wins over a
+    /// coding if the client gave it more weight.
+    pub fn negotiate(&self, supported: &[ContentCoding]) -> Negotiation {
+        let mut best: Option<(ContentCoding, u16)> = None;
+        for coding in supported {
+            let weight = match self.get_weight(coding.as_str()) {
+                Some(weight) if weight > 0 => weight,
+                _ => continue,
+            };
+            if best.map_or(true, |(_, best_weight)| weight > best_weight) {
+                best = Some((*coding, weight));
+            }
+        }
+        let identity = match self.get_weight("identity") {
+            Some(0) => None,
+            Some(weight) => Some(weight),
+            None => Some(0),
+        };
+        match (best, identity) {
+            (Some((coding, weight)), Some(identity)) if weight >= identity => {
+                Negotiation::Encode(coding)
+            }
+            (Some((coding, _)), None) => Negotiation::Encode(coding),
+            (_, Some(_)) => Negotiation::Identity,
+            (None, None) => Negotiation::NotAcceptable,
+        }
+    }
+}
+
+/// `0`, `1` or a fraction with up to three decimals, in thousandths.
+fn parse_qvalue(value: &str) -> Option<u16> {
+    let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let thousandths = format!("{:0<3}", fraction).parse::<u16>().ok()?;
+    match integer {
+        "0" => Some(thousandths),
+        "1" if thousandths == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+/// Applies `coding` to the body. In-memory bodies are compressed in one go; file and
+/// stream bodies are compressed on the fly as they are sent, so their compressed
+/// length isn't known up front.
+pub fn encode_content(content: Content, coding: ContentCoding, level: u32) -> io::Result<Content> {
+    let body = match content.body {
+        Body::Bytes(bytes) => Body::Bytes(encode_bytes(&bytes, coding, level)?),
+        Body::File { file, len } => Body::Stream(encode_reader(file.take(len), coding, level)?),
+        Body::Stream(reader) => Body::Stream(encode_reader(reader, coding, level)?),
+    };
+    Ok(Content {
+        content_type: content.content_type,
+        body,
+        encoding: Some(coding.as_str().to_owned()),
+    })
+}
+
+fn encode_bytes(bytes: &[u8], coding: ContentCoding, level: u32) -> io::Result<Vec<u8>> {
+    match coding {
+        ContentCoding::Brotli => {
+            let mut encoder = brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                level,
+                BROTLI_WINDOW_BITS,
+            );
+            encoder.write_all(bytes)?;
+            Ok(encoder.into_inner())
+        }
+        ContentCoding::Zstd => zstd::encode_all(bytes, level as i32),
+        ContentCoding::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), CompressionLevel::new(level));
+            encoder.write_all(bytes)?;
+            encoder.finish()
+        }
+        ContentCoding::Deflate => {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), CompressionLevel::new(level));
+            encoder.write_all(bytes)?;
+            encoder.finish()
+        }
+    }
+}
+
+fn encode_reader<R: Read + Send + 'static>(
+    reader: R,
+    coding: ContentCoding,
+    level: u32,
+) -> io::Result<Box<dyn Read + Send>> {
+    Ok(match coding {
+        ContentCoding::Brotli => Box::new(brotli::CompressorReader::new(
+            reader,
+            BROTLI_BUFFER_SIZE,
+            level,
+            BROTLI_WINDOW_BITS,
+        )),
+        ContentCoding::Zstd => Box::new(zstd::stream::read::Encoder::new(reader, level as i32)?),
+        ContentCoding::Gzip => Box::new(flate2::read::GzEncoder::new(
+            reader,
+            CompressionLevel::new(level),
+        )),
+        // HTTP's deflate is the zlib format, not raw deflate.
+        ContentCoding::Deflate => Box::new(flate2::read::ZlibEncoder::new(
+            reader,
+            CompressionLevel::new(level),
+        )),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(accept_encoding: &str) -> Negotiation {
+        let mut headers = HeaderMap::new();
+        headers.insert("Accept-Encoding", accept_encoding);
+        AcceptEncoding::from_headers(&headers)
+            .unwrap()
+            .negotiate(&ContentCoding::ALL)
+    }
+
+    #[test]
+    fn prefers_the_heaviest_coding() {
+        assert_eq!(
+            negotiate("gzip, br;q=0.5"),
+            Negotiation::Encode(ContentCoding::Gzip)
+        );
+        assert_eq!(
+            negotiate("DEFLATE;Q=0.9, gzip;q=0.8"),
+            Negotiation::Encode(ContentCoding::Deflate)
+        );
+        assert_eq!(
+            negotiate("gzip;q=2, deflate"),
+            Negotiation::Encode(ContentCoding::Deflate)
+        );
+    }
+
+    #[test]
+    fn breaks_ties_in_server_order() {
+        assert_eq!(
+            negotiate("gzip, br"),
+            Negotiation::Encode(ContentCoding::Brotli)
+        );
+        assert_eq!(negotiate("*"), Negotiation::Encode(ContentCoding::Brotli));
+        assert_eq!(
+            negotiate("gzip;q=0.5, identity;q=0.5"),
+            Negotiation::Encode(ContentCoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn falls_back_to_identity() {
+        assert_eq!(negotiate(""), Negotiation::Identity);
+        assert_eq!(negotiate("gzip;q=0"), Negotiation::Identity);
+        assert_eq!(negotiate("compress, identity"), Negotiation::Identity);
+        assert_eq!(negotiate("gzip;q=0.5, identity"), Negotiation::Identity);
+        assert_eq!(negotiate("*;q=0, identity"), Negotiation::Identity);
+    }
+
+    #[test]
+    fn refuses_when_identity_is_refused_too() {
+        assert_eq!(negotiate("identity;q=0"), Negotiation::NotAcceptable);
+        assert_eq!(negotiate("*;q=0"), Negotiation::NotAcceptable);
+        assert_eq!(
+            negotiate("compress, identity;q=0"),
+            Negotiation::NotAcceptable
+        );
+        assert_eq!(
+            negotiate("identity;q=0, zstd"),
+            Negotiation::Encode(ContentCoding::Zstd)
+        );
+        assert_eq!(
+            negotiate("*;q=0, gzip;q=0.1"),
+            Negotiation::Encode(ContentCoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn tells_whether_identity_is_refused() {
+        let accepts_identity = |accept_encoding: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("Accept-Encoding", accept_encoding);
+            AcceptEncoding::from_headers(&headers)
+                .unwrap()
+                .accepts_identity()
+        };
+        assert!(accepts_identity(""));
+        assert!(accepts_identity("gzip;q=0"));
+        assert!(accepts_identity("*;q=0, identity;q=0.1"));
+        assert!(!accepts_identity("identity;q=0, gzip"));
+        assert!(!accepts_identity("*;q=0, gzip"));
+    }
+
+    #[test]
+    fn leaves_the_choice_to_the_server_without_a_header() {
+        assert!(AcceptEncoding::from_headers(&HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn parses_qvalues() {
+        assert_eq!(parse_qvalue("0"), Some(0));
+        assert_eq!(parse_qvalue("0.5"), Some(500));
+        assert_eq!(parse_qvalue("0.123"), Some(123));
+        assert_eq!(parse_qvalue("1"), Some(1000));
+        assert_eq!(parse_qvalue("1.000"), Some(1000));
+        for invalid in ["", ".5", "0.1234", "1.5", "2", "0.x", "-0"] {
+            assert_eq!(parse_qvalue(invalid), None, "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::compression::CompressionPolicy;
+use crate::files::{FileStore, FileStoreOptions};
+use crate::http::reader::{
+    DEFAULT_BODY_TIMEOUT, DEFAULT_HEADER_TIMEOUT, DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_HEADER_SIZE,
+};
+use crate::log::LogLevel;
+use crate::middleware::{DEFAULT_CORS_HEADERS, DEFAULT_CORS_METHODS};
+use crate::mime::MimeTypes;
+use crate::pool::OverloadPolicy;
+
+pub const USAGE: &str = "\
+Usage: codecrafters-http-server [OPTIONS]
+
+Options:
+  --directory <DIR>       Serve and store /files/ in DIR (disabled if not given)
+  --bind <ADDR>           Address to listen on [default: 127.0.0.1]
+  --port <PORT>           Port to listen on [default: 4221]
+  --threads <N>           Worker threads serving connections [default: 32]
+  --queue-size <N>        Accepted connections waiting for a worker [default: 128]
+  --overload <POLICY>     With a full queue, wait for room or reject with 503 [default: wait]
+  --shutdown-timeout <SECS>
+                          Time in-flight requests get to finish on SIGINT/SIGTERM [default: 30]
+  --idle-timeout <SECS>   Time a keep-alive connection may wait for its next request [default: 5]
+  --header-timeout <SECS> Time a client gets to send a whole request header section [default: 10]
+  --body-timeout <SECS>   Time a client gets to send a whole request body [default: 60]
+  --max-header-size <BYTES>
+                          Largest accepted request header section [default: 8192]
+  --max-body-size <BYTES> Largest accepted request body [default: 16777216]
+  --log-level <LEVEL>     One of error, warn, info, debug [default: info]
+  --no-overwrite          Refuse POSTs to /files/ that would replace a file
+  --follow-symlinks       Follow symlinks under DIR that stay inside DIR
+  --mime <EXT=TYPE>       Serve files ending in .EXT from /files/ as TYPE; may be repeated
+  --sniff-mime            Tell the type of files without a known extension from their contents
+  --compression-min-size <BYTES>
+                          Smallest body worth compressing [default: 256]
+  --compress-types <TYPES>
+                          Comma-separated media types to compress, e.g. text/*,application/json
+                          [default: all but --no-compress-types]
+  --no-compress-types <TYPES>
+                          Comma-separated media types never to compress
+                          [default: already compressed images, audio, video, fonts, archives,
+                          and application/octet-stream]
+  --compression-level <TYPE=LEVEL>
+                          Compression level for a media type; may be repeated
+  --cors-origin <ORIGIN>  Allow cross-origin requests from ORIGIN (or *)
+  --cors-methods <METHODS>
+                          Comma-separated methods cross-origin requests may use
+                          [default: GET,HEAD,POST,PUT,DELETE]
+  --cors-headers <HEADERS>
+                          Comma-separated headers cross-origin requests may send
+                          [default: Authorization,Content-Type,Range]
+  --auth-token <TOKEN>    Require `Authorization: Bearer TOKEN` on every request
+  --help                  Print this help and exit";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("help requested")]
+    HelpRequested,
+    #[error("unknown argument: {0}")]
+    UnknownArgument(String),
+    #[error("missing value for {0}")]
+    MissingValue(String),
+    #[error("invalid value {value:?} for {flag}: {reason}")]
+    InvalidValue {
+        flag: String,
+        value: String,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub directory: Option<PathBuf>,
+    pub bind: IpAddr,
+    pub port: u16,
+    pub threads: usize,
+    pub queue_size: usize,
+    pub overload_policy: OverloadPolicy,
+    pub shutdown_timeout: Duration,
+    pub idle_timeout: Duration,
+    pub header_timeout: Duration,
+    pub body_timeout: Duration,
+    pub max_header_size: usize,
+    pub max_body_size: usize,
+    pub log_level: LogLevel,
+    pub file_store_options: FileStoreOptions,
+    pub mime_types: MimeTypes,
+    pub compression_policy: CompressionPolicy,
+    pub cors_origin: Option<String>,
+    pub cors_methods: Vec<String>,
+    pub cors_headers: Vec<String>,
+    pub auth_token: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            bind: IpAddr::from([127, 0, 0, 1]),
+            port: 4221,
+            threads: 32,
+            queue_size: 128,
+            overload_policy: OverloadPolicy::Wait,
+            shutdown_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(5),
+            header_timeout: DEFAULT_HEADER_TIMEOUT,
+            body_timeout: DEFAULT_BODY_TIMEOUT,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            log_level: LogLevel::Info,
+            file_store_options: FileStoreOptions::default(),
+            mime_types: MimeTypes::default(),
+            compression_policy: CompressionPolicy::default(),
+            cors_origin: None,
+            cors_methods: DEFAULT_CORS_METHODS.map(str::to_owned).to_vec(),
+            cors_headers: DEFAULT_CORS_HEADERS.map(str::to_owned).to_vec(),
+            auth_token: None,
+        }
+    }
+}
+
+impl Config {
+    /// Parses and validates command-line arguments, without the program name.
+    /// Values can be given as `--flag value` or `--flag=value`; on/off flags take none.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| ConfigError::MissingValue(flag.clone()))
+            };
+            // On/off flags take no value, so `--follow-symlinks=false` can't be
+            // mistaken for turning the option off.
+            let switch = || match &inline_value {
+                Some(value) => Err(invalid(&flag, value, "takes no value")),
+                None => Ok(()),
+            };
+            match flag.as_str() {
+                "--help" | "-h" => {
+                    switch()?;
+                    return Err(ConfigError::HelpRequested);
+                }
+                "--directory" => {
+                    let directory = PathBuf::from(value()?);
+                    if !directory.is_dir() {
+                        return Err(invalid(
+                            &flag,
+                            &directory.to_string_lossy(),
+                            "not a directory",
+                        ));
+                    }
+                    config.directory = Some(directory);
+                }
+                "--bind" => config.bind = parse(&flag, &value()?)?,
+                "--port" => config.port = parse(&flag, &value()?)?,
+                "--threads" => {
+                    let raw = value()?;
+                    config.threads = parse(&flag, &raw)?;
+                    if config.threads == 0 {
+                        return Err(invalid(&flag, &raw, "must be at least 1"));
+                    }
+                }
+                "--queue-size" => config.queue_size = parse(&flag, &value()?)?,
+                "--overload" => config.overload_policy = parse(&flag, &value()?)?,
+                "--shutdown-timeout" => {
+                    config.shutdown_timeout = Duration::from_secs(parse(&flag, &value()?)?)
+                }
+                "--idle-timeout" => {
+                    let raw = value()?;
+                    config.idle_timeout = Duration::from_secs(parse(&flag, &raw)?);
+                    if config.idle_timeout.is_zero() {
+                        return Err(invalid(&flag, &raw, "must be at least 1"));
+                    }
+                }
+                "--header-timeout" => {
+                    let raw = value()?;
+                    config.header_timeout = Duration::from_secs(parse(&flag, &raw)?);
+                    if config.header_timeout.is_zero() {
+                        return Err(invalid(&flag, &raw, "must be at least 1"));
+                    }
+                }
+                "--body-timeout" => {
+                    let raw = value()?;
+                    config.body_timeout = Duration::from_secs(parse(&flag, &raw)?);
+                    if config.body_timeout.is_zero() {
+                        return Err(invalid(&flag, &raw, "must be at least 1"));
+                    }
+                }
+                "--max-header-size" => config.max_header_size = parse(&flag, &value()?)?,
+                "--max-body-size" => config.max_body_size = parse(&flag, &value()?)?,
+                "--log-level" => config.log_level = parse(&flag, &value()?)?,
+                "--no-overwrite" => {
+                    switch()?;
+                    config.file_store_options.overwrite_on_post = false;
+                }
+                "--follow-symlinks" => {
+                    switch()?;
+                    config.file_store_options.follow_symlinks = true;
+                }
+                "--mime" => {
+                    let raw = value()?;
+                    let (extension, media_type) = raw
+                        .split_once('=')
+                        .ok_or_else(|| invalid(&flag, &raw, "expected EXT=TYPE"))?;
+                    let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+                    let media_type = parse(&flag, media_type)?;
+                    config.mime_types.overrides.insert(extension, media_type);
+                }
+                "--sniff-mime" => {
+                    switch()?;
+                    config.mime_types.sniff = true;
+                }
+                "--compression-min-size" => {
+                    config.compression_policy.min_size = parse(&flag, &value()?)?
+                }
+                "--compress-types" => {
+                    config.compression_policy.allowed_types = parse_list(&value()?)
+                }
+                "--no-compress-types" => {
+                    config.compression_policy.denied_types = parse_list(&value()?)
+                }
+                "--compression-level" => {
+                    let raw = value()?;
+                    let (media_type, level) = raw
+                        .split_once('=')
+                        .ok_or_else(|| invalid(&flag, &raw, "expected TYPE=LEVEL"))?;
+                    let level = parse(&flag, level)?;
+                    config
+                        .compression_policy
+                        .levels
+                        .push((media_type.to_owned(), level));
+                }
+                "--cors-origin" => config.cors_origin = Some(value()?),
+                "--cors-methods" => config.cors_methods = parse_list(&value()?),
+                "--cors-headers" => config.cors_headers = parse_list(&value()?),
+                "--auth-token" => config.auth_token = Some(value()?),
+                _ => return Err(ConfigError::UnknownArgument(flag)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The store behind `/files/`, if a directory was given.
+    pub fn get_file_store(&self) -> Option<FileStore> {
+        self.directory
+            .as_ref()
+            .map(|directory| FileStore::new(directory, self.file_store_options.clone()))
+    }
+}
+
+fn parse<T: FromStr>(flag: &str, value: &str) -> Result<T, ConfigError>
+where
+    T::Err: ToString,
+{
+    value
+        .parse()
+        .map_err(|err: T::Err| invalid(flag, value, &err.to_string()))
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn invalid(flag: &str, value: &str, reason: &str) -> ConfigError {
+    ConfigError::InvalidValue {
+        flag: flag.to_owned(),
+        value: value.to_owned(),
+        reason: reason.to_owned(),
+    }
+}
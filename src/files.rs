@@ -0,0 +1,297 @@
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+/// Whether a write made a new file or replaced an existing one.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Created,
+    Replaced,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileStoreOptions {
+    /// When unset, POST refuses to replace an existing file and fails with
+    /// `io::ErrorKind::AlreadyExists`; PUT always replaces.
+    pub overwrite_on_post: bool,
+    /// When set, symlinks inside the root are followed as long as they resolve to
+    /// somewhere inside the root. When unset, any symlink is refused.
+    pub follow_symlinks: bool,
+}
+
+impl Default for FileStoreOptions {
+    fn default() -> Self {
+        Self {
+            overwrite_on_post: true,
+            follow_symlinks: false,
+        }
+    }
+}
+
+/// The directory behind the `/files/` endpoint, used as a small blob store.
+///
+/// File names come straight from request paths, so every operation resolves them
+/// with `resolve`: names that would leave the root fail with
+/// `io::ErrorKind::PermissionDenied`, empty ones or ones with a NUL byte with
+/// `io::ErrorKind::InvalidInput`.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    root: PathBuf,
+    options: FileStoreOptions,
+}
+
+impl FileStore {
+    pub fn new(root: impl Into<PathBuf>, options: FileStoreOptions) -> Self {
+        Self {
+            root: root.into(),
+            options,
+        }
+    }
+
+    /// Maps a name from a request path, already percent-decoded, to a location under the root.
+    pub fn resolve(&self, name: &str) -> io::Result<PathBuf> {
+        let root = fs::canonicalize(&self.root)?;
+
+        let mut segments: Vec<&str> = Vec::new();
+        for segment in name.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    if segments.pop().is_none() {
+                        return Err(forbidden(name));
+                    }
+                }
+                _ if segment.contains('\0') => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("file name contains a NUL byte: {}", name),
+                    ));
+                }
+                _ => segments.push(segment),
+            }
+        }
+        if segments.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file name is empty",
+            ));
+        }
+
+        // Lexically the path can't leave the root any more; only symlinks on the way can.
+        let mut path = root.clone();
+        for segment in segments {
+            path.push(segment);
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            if metadata.file_type().is_symlink()
+                && !(self.options.follow_symlinks && fs::canonicalize(&path)?.starts_with(&root))
+            {
+                return Err(forbidden(name));
+            }
+        }
+        Ok(path)
+    }
+
+    pub fn open(&self, name: &str) -> io::Result<File> {
+        File::open(self.resolve(name)?)
+    }
+
+    /// Stores `body` as `name` on behalf of a POST.
+    pub fn post(&self, name: &str, body: &[u8]) -> io::Result<WriteOutcome> {
+        if self.options.overwrite_on_post {
+            self.put(name, body)
+        } else {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.resolve(name)?)?;
+            file.write_all(body)?;
+            Ok(WriteOutcome::Created)
+        }
+    }
+
+    /// Stores `body` as `name`, replacing any existing file.
+    pub fn put(&self, name: &str, body: &[u8]) -> io::Result<WriteOutcome> {
+        let path = self.resolve(name)?;
+        let (mut file, outcome) = match OpenOptions::new().write(true).create_new(true).open(&path)
+        {
+            Ok(file) => (file, WriteOutcome::Created),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                (File::create(&path)?, WriteOutcome::Replaced)
+            }
+            Err(err) => return Err(err),
+        };
+        file.write_all(body)?;
+        Ok(outcome)
+    }
+
+    pub fn delete(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.resolve(name)?)
+    }
+}
+
+/// A strong entity tag for a file's current contents, made of its modification time
+/// and size, as most servers do rather than hashing every file they serve.
+pub fn get_etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", modified.as_nanos(), metadata.len())
+}
+
+fn forbidden(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("file name escapes the root directory: {}", name),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::http::uri::percent_decode;
+
+    /// A directory with a `root` to serve and an `outside` next to it, removed on drop.
+    struct Sandbox(PathBuf);
+
+    impl Sandbox {
+        fn new() -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "file-store-{}-{}",
+                process::id(),
+                COUNT.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::create_dir_all(dir.join("root/real")).unwrap();
+            fs::create_dir_all(dir.join("outside")).unwrap();
+            fs::write(dir.join("root/real/f.txt"), "inside").unwrap();
+            fs::write(dir.join("outside/secret.txt"), "outside").unwrap();
+            Self(fs::canonicalize(dir).unwrap())
+        }
+
+        fn root(&self) -> PathBuf {
+            self.0.join("root")
+        }
+
+        fn store(&self, follow_symlinks: bool) -> FileStore {
+            FileStore::new(
+                self.root(),
+                FileStoreOptions {
+                    follow_symlinks,
+                    ..FileStoreOptions::default()
+                },
+            )
+        }
+
+        #[cfg(unix)]
+        fn link(&self, name: &str, target: &Path) {
+            std::os::unix::fs::symlink(target, self.root().join(name)).unwrap();
+        }
+    }
+
+    impl Drop for Sandbox {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn error_kind(result: io::Result<PathBuf>) -> io::ErrorKind {
+        result.unwrap_err().kind()
+    }
+
+    #[test]
+    fn resolves_names_under_the_root() {
+        let sandbox = Sandbox::new();
+        let store = sandbox.store(false);
+        assert_eq!(
+            store.resolve("real/f.txt").unwrap(),
+            sandbox.root().join("real/f.txt")
+        );
+        assert_eq!(
+            store.resolve("/real//./f.txt").unwrap(),
+            sandbox.root().join("real/f.txt")
+        );
+        assert_eq!(store.resolve("a/../b").unwrap(), sandbox.root().join("b"));
+        assert_eq!(
+            store.resolve("new.txt").unwrap(),
+            sandbox.root().join("new.txt")
+        );
+    }
+
+    #[test]
+    fn refuses_names_above_the_root() {
+        let sandbox = Sandbox::new();
+        let store = sandbox.store(true);
+        for name in ["..", "../outside/secret.txt", "a/../../x", "real/../.."] {
+            assert_eq!(
+                error_kind(store.resolve(name)),
+                io::ErrorKind::PermissionDenied,
+                "{:?}",
+                name
+            );
+        }
+        let decoded = percent_decode("..%2F..%2Foutside%2Fsecret.txt").unwrap();
+        assert_eq!(
+            error_kind(store.resolve(&decoded)),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn refuses_empty_names_and_nul_bytes() {
+        let sandbox = Sandbox::new();
+        let store = sandbox.store(false);
+        for name in ["", "/", "./.", "a/.."] {
+            assert_eq!(
+                error_kind(store.resolve(name)),
+                io::ErrorKind::InvalidInput,
+                "{:?}",
+                name
+            );
+        }
+        assert_eq!(
+            error_kind(store.resolve("real/f.txt\0.png")),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_unless_following_them() {
+        let sandbox = Sandbox::new();
+        sandbox.link("link", &sandbox.root().join("real"));
+        assert_eq!(
+            error_kind(sandbox.store(false).resolve("link/f.txt")),
+            io::ErrorKind::PermissionDenied
+        );
+        let path = sandbox.store(true).resolve("link/f.txt").unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "inside");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_symlinks_out_of_the_root_even_when_following_them() {
+        let sandbox = Sandbox::new();
+        sandbox.link("escape", &sandbox.0.join("outside"));
+        sandbox.link("secret.txt", &sandbox.0.join("outside/secret.txt"));
+        let store = sandbox.store(true);
+        for name in ["escape/secret.txt", "escape", "secret.txt"] {
+            assert_eq!(
+                error_kind(store.resolve(name)),
+                io::ErrorKind::PermissionDenied,
+                "{:?}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::io;
+
+use thiserror::Error;
+
+use crate::http::request::Request;
+use crate::http::response::{Body, Content, Response};
+use crate::http::{ContentType, ParseError, Status};
+use crate::{log_debug, log_error};
+
+/// Why a handler could not produce the response it was asked for.
+#[derive(Debug, Error)]
+pub enum HttpError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error(transparent)]
+    Malformed(#[from] ParseError),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+impl HttpError {
+    pub fn get_status(&self) -> Status {
+        match self {
+            Self::BadRequest(_) => Status::BadRequest,
+            Self::Malformed(err) => err.get_status(),
+            Self::Forbidden(_) => Status::Forbidden,
+            Self::NotFound(_) => Status::NotFound,
+            Self::Conflict(_) => Status::Conflict,
+            Self::Io(_) => Status::InternalServerError,
+        }
+    }
+}
+
+/// Sorts I/O errors by what they say about the request; anything else is the server's fault.
+impl From<io::Error> for HttpError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => Self::NotFound(err.to_string()),
+            io::ErrorKind::AlreadyExists => Self::Conflict(err.to_string()),
+            io::ErrorKind::PermissionDenied => Self::Forbidden(err.to_string()),
+            io::ErrorKind::InvalidInput => Self::BadRequest(err.to_string()),
+            _ => Self::Io(err),
+        }
+    }
+}
+
+/// Client errors explain themselves in the body; server errors are logged and
+/// only name the status, so internals don't leak.
+impl From<HttpError> for Response {
+    fn from(err: HttpError) -> Self {
+        let status = err.get_status();
+        let message = if matches!(err, HttpError::Io(_)) {
+            log_error!("Failed to handle a request: {}", err);
+            status.to_string()
+        } else {
+            log_debug!("Rejecting request: {}", err);
+            err.to_string()
+        };
+        Response::new(status).with_content(Content {
+            content_type: ContentType::TEXT_PLAIN,
+            body: Body::Bytes(message.into_bytes()),
+            encoding: None,
+        })
+    }
+}
+
+/// What handler functions may return: a response, or an error that becomes one.
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for Result<Response, HttpError> {
+    fn into_response(self) -> Response {
+        self.unwrap_or_else(Response::from)
+    }
+}
+
+/// Turns a request into a response. Implemented for plain functions and closures
+/// taking `&Request` and returning an `IntoResponse`, for `Router` and for `Chain`.
+///
+/// The request is passed mutably so middleware can amend it before passing it on.
+pub trait Handler: Send + Sync {
+    fn handle(&self, req: &mut Request) -> Response;
+}
+
+impl<F, R> Handler for F
+where
+    F: Fn(&Request) -> R + Send + Sync,
+    R: IntoResponse,
+{
+    fn handle(&self, req: &mut Request) -> Response {
+        self(req).into_response()
+    }
+}
+
+/// A layer around a handler. It can inspect or amend the request, answer on its
+/// own without calling `next`, and inspect or amend whatever `next` returns.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: &mut Request, next: &dyn Handler) -> Response;
+}
+
+/// An endpoint wrapped in middleware. Middleware added first is the outermost:
+/// it sees the request first and the response last.
+pub struct Chain {
+    middlewares: Vec<Box<dyn Middleware>>,
+    endpoint: Box<dyn Handler>,
+}
+
+impl Chain {
+    pub fn new<H: Handler + 'static>(endpoint: H) -> Self {
+        Self {
+            middlewares: Vec::new(),
+            endpoint: Box::new(endpoint),
+        }
+    }
+
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+}
+
+impl Handler for Chain {
+    fn handle(&self, req: &mut Request) -> Response {
+        Next {
+            middlewares: &self.middlewares,
+            endpoint: self.endpoint.as_ref(),
+        }
+        .handle(req)
+    }
+}
+
+/// The rest of a chain, as seen by one of its middlewares.
+struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware>],
+    endpoint: &'a dyn Handler,
+}
+
+impl Handler for Next<'_> {
+    fn handle(&self, req: &mut Request) -> Response {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                req,
+                &Next {
+                    middlewares: rest,
+                    endpoint: self.endpoint,
+                },
+            ),
+            None => self.endpoint.handle(req),
+        }
+    }
+}
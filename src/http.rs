@@ -1,97 +1,1947 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// Why a request, or a part of one, could not be parsed. Lines and columns are
+/// counted from 1, starting with the request line.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error("the request is empty")]
+    EmptyRequest,
+    #[error(
+        "invalid request line, expected method, target and version separated by single spaces: {0}"
+    )]
+    InvalidRequestLine(String),
+    #[error("invalid character in the method at column {column}")]
+    InvalidMethod { column: usize },
+    #[error("invalid character in the request target at column {column}")]
+    InvalidTarget { column: usize },
+    #[error("request target is not origin-form, absolute-form or * for OPTIONS: {0}")]
+    InvalidTargetForm(String),
+    #[error("invalid HTTP version: {0}")]
+    InvalidVersion(String),
+    #[error("unsupported HTTP version: {0}")]
+    UnsupportedVersion(String),
+    #[error("line {line}: bare CR or LF at column {column}")]
+    InvalidLineEnding { line: usize, column: usize },
+    #[error("line {line}: obsolete line folding")]
+    ObsoleteLineFolding { line: usize },
+    #[error("line {line}: missing colon after the header name")]
+    MissingColon { line: usize },
+    #[error("line {line}: whitespace between the header name and the colon")]
+    WhitespaceBeforeColon { line: usize },
+    #[error("line {line}: invalid character in the header name at column {column}")]
+    InvalidHeaderName { line: usize, column: usize },
+    #[error("line {line}: invalid character in the header value at column {column}")]
+    InvalidHeaderValue { line: usize, column: usize },
+    #[error("missing Host header")]
+    MissingHost,
+    #[error("more than one Host header")]
+    RepeatedHost,
+    #[error("both Content-Length and Transfer-Encoding are present")]
+    ContentLengthWithTransferEncoding,
+    #[error("invalid Content-Length: {0}")]
+    InvalidContentLength(String),
+    #[error("chunked applied more than once")]
+    RepeatedChunked,
+    #[error("invalid chunk size: {0}")]
+    InvalidChunkSize(String),
+    #[error("missing CRLF after chunk data")]
+    MissingChunkTerminator,
+    #[error("chunk line exceeds {0} bytes")]
+    ChunkLineTooLong(usize),
+    #[error("invalid trailer: {0}")]
+    InvalidTrailer(String),
+    #[error("trailer section exceeds {0} bytes")]
+    TrailersTooLarge(usize),
+    #[error("incomplete chunked body")]
+    IncompleteBody,
+    #[error("invalid percent-encoding: {0}")]
+    InvalidPercentEncoding(String),
+    #[error("invalid status: {0}")]
+    InvalidStatus(String),
+    #[error("invalid media type: {0}")]
+    InvalidMediaType(String),
+}
+
+impl ParseError {
+    /// The status to answer a request that failed to parse with.
+    pub fn get_status(&self) -> Status {
+        match self {
+            Self::UnsupportedVersion(_) => Status::HttpVersionNotSupported,
+            _ => Status::BadRequest,
+        }
+    }
+}
+
+pub mod headers {
+
+    use super::{ContentType, ParseError};
+
+    /// Header fields in the order they were added. Names keep the case they were
+    /// given in but are looked up case-insensitively, and a name can appear more
+    /// than once, as `Set-Cookie` or `Accept` often do.
+    #[derive(Debug, Clone, Default)]
+    pub struct HeaderMap {
+        entries: Vec<(String, String)>,
+    }
+
+    impl HeaderMap {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn len(&self) -> usize {
+            self.entries.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.entries.is_empty()
+        }
+
+        pub fn contains(&self, name: &str) -> bool {
+            self.get(name).is_some()
+        }
+
+        /// The first value of `name`.
+        pub fn get(&'_ self, name: &str) -> Option<&'_ str> {
+            self.entries
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+
+        /// Every value of `name`, in the order they were added.
+        pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+            self.entries
+                .iter()
+                .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+
+        /// The elements of a comma-separated list header, across all of its fields.
+        /// Empty elements are skipped.
+        pub fn get_list(&'_ self, name: &str) -> Vec<&'_ str> {
+            self.entries
+                .iter()
+                .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+                .flat_map(|(_, value)| value.split(','))
+                .map(str::trim)
+                .filter(|element| !element.is_empty())
+                .collect()
+        }
+
+        /// Replaces every value of `name` with `value`, keeping the position of the
+        /// first one.
+        pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+            let name = name.into();
+            match self
+                .entries
+                .iter()
+                .position(|(key, _)| key.eq_ignore_ascii_case(&name))
+            {
+                Some(pos) => {
+                    self.entries[pos].1 = value.into();
+                    let rest = self.entries.split_off(pos + 1);
+                    self.entries.extend(
+                        rest.into_iter()
+                            .filter(|(key, _)| !key.eq_ignore_ascii_case(&name)),
+                    );
+                }
+                None => self.entries.push((name, value.into())),
+            }
+        }
+
+        /// Adds a value for `name` after any it already has.
+        pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+            self.entries.push((name.into(), value.into()));
+        }
+
+        /// Adds `element` to the comma-separated list `name` unless it is there already,
+        /// e.g. a header name to `Vary`.
+        pub fn add_to_list(&mut self, name: &str, element: &str) {
+            let mut elements = self.get_list(name);
+            if elements
+                .iter()
+                .any(|listed| listed.eq_ignore_ascii_case(element))
+            {
+                return;
+            }
+            elements.push(element);
+            let list = elements.join(", ");
+            self.insert(name, list);
+        }
+
+        /// Removes `name` and returns its first value.
+        pub fn remove(&mut self, name: &str) -> Option<String> {
+            let mut removed = None;
+            self.entries.retain(|(key, value)| {
+                if !key.eq_ignore_ascii_case(name) {
+                    return true;
+                }
+                removed.get_or_insert_with(|| value.clone());
+                false
+            });
+            removed
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+            self.entries
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+        }
+
+        /// `Content-Length`, which may be repeated only with the same value.
+        pub fn get_content_length(&self) -> Result<Option<u64>, ParseError> {
+            let mut content_length = None;
+            for value in self.get_list("Content-Length") {
+                let len = value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|_| value.bytes().all(|b| b.is_ascii_digit()))
+                    .ok_or_else(|| ParseError::InvalidContentLength(value.to_string()))?;
+                if content_length.is_some_and(|previous| previous != len) {
+                    return Err(ParseError::InvalidContentLength(value.to_string()));
+                }
+                content_length = Some(len);
+            }
+            Ok(content_length)
+        }
+
+        /// The parsed `Content-Type`; an unparseable one is an error rather than
+        /// something to guess around.
+        pub fn get_content_type(&self) -> Result<Option<ContentType>, ParseError> {
+            self.get("Content-Type").map(str::parse).transpose()
+        }
+
+        pub fn get_host(&'_ self) -> Option<&'_ str> {
+            self.get("Host")
+        }
+
+        pub fn get_user_agent(&'_ self) -> Option<&'_ str> {
+            self.get("User-Agent")
+        }
+
+        /// The transfer codings applied to the body, in the order they were applied.
+        pub fn get_transfer_codings(&self) -> Vec<&str> {
+            self.get_list("Transfer-Encoding")
+        }
+
+        /// Whether `Connection` lists `option`, e.g. `close` or `keep-alive`.
+        pub fn has_connection_option(&self, option: &str) -> bool {
+            self.get_list("Connection")
+                .iter()
+                .any(|token| token.eq_ignore_ascii_case(option))
+        }
+    }
+
+    impl<'a> IntoIterator for &'a HeaderMap {
+        type Item = (&'a str, &'a str);
+        type IntoIter = Box<dyn Iterator<Item = (&'a str, &'a str)> + 'a>;
+
+        fn into_iter(self) -> Self::IntoIter {
+            Box::new(self.iter())
+        }
+    }
+}
+
 pub mod request {
 
-    use std::collections::HashMap;
+    use std::collections::HashMap;
+
+    use bytes::{Bytes, BytesMut};
+
+    use super::chunked::ChunkedDecoder;
+    use super::headers::HeaderMap;
+    use super::uri::{RequestTarget, TargetForm};
+    use super::{is_tchar, ContentType, HttpMethod, ParseError};
+
+    pub const HEADER_TERMINATOR: &[u8] = b"\r\n\r\n";
+    const CRLF: &[u8] = b"\r\n";
+
+    #[derive(Debug, Default)]
+    pub struct Request {
+        method: HttpMethod,
+        target: RequestTarget,
+        http_version: String,
+        headers: HeaderMap,
+        body: Bytes,
+        trailers: HeaderMap,
+        params: HashMap<String, String>,
+    }
+
+    impl Request {
+        pub fn get_method(&'_ self) -> &'_ HttpMethod {
+            &self.method
+        }
+
+        /// The path as sent, still percent-encoded and without the query.
+        pub fn get_path(&'_ self) -> &'_ str {
+            &self.target.path
+        }
+
+        /// The path split on `/`, each segment percent-decoded.
+        pub fn get_path_segments(&'_ self) -> &'_ [String] {
+            &self.target.segments
+        }
+
+        /// The query as sent, without the `?`.
+        pub fn get_query(&'_ self) -> Option<&'_ str> {
+            self.target.query.as_deref()
+        }
+
+        /// The decoded query parameters in the order they were sent.
+        pub fn get_query_params(&'_ self) -> &'_ [(String, String)] {
+            &self.target.query_params
+        }
+
+        /// The first value of the query parameter `name`.
+        pub fn get_query_param(&'_ self, name: &str) -> Option<&'_ str> {
+            self.get_query_param_values(name).into_iter().next()
+        }
+
+        /// Every value of the query parameter `name`, e.g. both of `?tag=a&tag=b`.
+        pub fn get_query_param_values(&'_ self, name: &str) -> Vec<&'_ str> {
+            self.target
+                .query_params
+                .iter()
+                .filter(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+                .collect()
+        }
+
+        pub fn get_target_form(&self) -> TargetForm {
+            self.target.form
+        }
+
+        /// The authority of an absolute-form target, which takes precedence over `Host`.
+        pub fn get_authority(&'_ self) -> Option<&'_ str> {
+            self.target
+                .authority
+                .as_deref()
+                .or_else(|| self.headers.get_host())
+        }
+
+        pub fn get_http_version(&'_ self) -> &'_ str {
+            &self.http_version
+        }
+
+        pub fn get_headers(&'_ self) -> &'_ HeaderMap {
+            &self.headers
+        }
+
+        /// The media type of the body, or `None` if the client didn't say.
+        pub fn get_content_type(&self) -> Result<Option<ContentType>, ParseError> {
+            self.headers.get_content_type()
+        }
+
+        /// Whether the body is declared as `content_type`, parameters aside. A missing
+        /// or malformed `Content-Type` matches nothing.
+        pub fn has_content_type(&self, content_type: &ContentType) -> bool {
+            self.get_content_type()
+                .ok()
+                .flatten()
+                .is_some_and(|declared| declared.has_essence_of(content_type))
+        }
+
+        /// A segment captured by the matched route, e.g. `id` for `/users/:id`.
+        pub fn get_param(&'_ self, name: &str) -> Option<&'_ str> {
+            self.params.get(name).map(String::as_str)
+        }
+
+        pub fn get_params(&'_ self) -> &'_ HashMap<String, String> {
+            &self.params
+        }
+
+        pub fn set_params(&mut self, params: HashMap<String, String>) {
+            self.params = params;
+        }
+
+        /// Case-insensitive lookup of a single header value.
+        pub fn get_header(&'_ self, name: &str) -> Option<&'_ str> {
+            self.headers.get(name)
+        }
+
+        pub fn get_body(&'_ self) -> &'_ Bytes {
+            &self.body
+        }
+
+        pub fn set_body(&mut self, body: Bytes) {
+            self.body = body;
+        }
+
+        /// Trailer fields sent after a chunked body.
+        pub fn get_trailers(&'_ self) -> &'_ HeaderMap {
+            &self.trailers
+        }
+
+        pub fn set_trailers(&mut self, trailers: HeaderMap) {
+            self.trailers = trailers;
+        }
+
+        /// The transfer codings applied to the body, in the order they were applied.
+        pub fn get_transfer_codings(&self) -> Vec<&str> {
+            self.headers.get_transfer_codings()
+        }
+
+        /// Whether the body is framed by the chunked transfer coding.
+        pub fn is_chunked(&self) -> bool {
+            self.get_transfer_codings()
+                .last()
+                .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+        }
+
+        /// Whether the client expects the connection to stay open after this request:
+        /// HTTP/1.1 defaults to persistent connections, HTTP/1.0 has to opt in.
+        pub fn wants_keep_alive(&self) -> bool {
+            if self.headers.has_connection_option("close") {
+                false
+            } else if self.http_version == "HTTP/1.0" {
+                self.headers.has_connection_option("keep-alive")
+            } else {
+                true
+            }
+        }
+
+        /// Parses a complete request. Everything after the header section is the body,
+        /// taken verbatim unless it is chunked.
+        pub fn from_raw(input: &[u8]) -> Result<Self, ParseError> {
+            let head_len = find_header_end(input).unwrap_or(input.len());
+            let mut request = Self::from_head(&input[..head_len])?;
+            if request.is_chunked() {
+                let mut decoder = ChunkedDecoder::default();
+                if !decoder.decode(&mut BytesMut::from(&input[head_len..]))? {
+                    return Err(ParseError::IncompleteBody);
+                }
+                let (body, trailers) = decoder.finish();
+                request.set_body(body);
+                request.set_trailers(trailers);
+            } else {
+                request.set_body(Bytes::copy_from_slice(&input[head_len..]));
+            }
+            Ok(request)
+        }
+
+        /// Parses the request line and header section as RFC 9112 has them: single
+        /// spaces in the request line, `name: value` fields with optional whitespace around
+        /// the value, CRLF line endings and no line folding. The body is left to the caller.
+        pub fn from_head(input: &[u8]) -> Result<Self, ParseError> {
+            // Empty lines ahead of the request line are allowed, e.g. left over after a body.
+            let mut input = input;
+            while let Some(rest) = input.strip_prefix(CRLF) {
+                input = rest;
+            }
+            let mut lines = split_lines(input)
+                .enumerate()
+                .map(|(i, line)| (i + 1, line));
+            let (_, request_line) = lines
+                .next()
+                .filter(|(_, line)| !line.is_empty())
+                .ok_or(ParseError::EmptyRequest)?;
+            check_line_ending(1, request_line)?;
+            let (method, target, http_version) = parse_request_line(request_line)?;
+            let target = RequestTarget::parse(&target, &method)?;
+
+            let mut headers = HeaderMap::new();
+            for (number, line) in lines {
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = parse_header(number, line)?;
+                headers.append(name, value);
+            }
+            check_framing(&headers)?;
+            if http_version == "HTTP/1.1" && !headers.contains("Host") {
+                return Err(ParseError::MissingHost);
+            }
+            if headers.get_all("Host").count() > 1 {
+                return Err(ParseError::RepeatedHost);
+            }
+
+            Ok(Self {
+                method,
+                target,
+                http_version,
+                headers,
+                body: Bytes::new(),
+                trailers: HeaderMap::new(),
+                params: HashMap::new(),
+            })
+        }
+    }
+
+    /// The error `from_head` will give for the first LF without a CR before it in a
+    /// head that is still being read. Looking out for it while reading keeps a head
+    /// with bare LFs from waiting for a CRLFCRLF that never comes.
+    pub fn find_bare_lf(input: &[u8]) -> Option<ParseError> {
+        let mut input = input;
+        while let Some(rest) = input.strip_prefix(CRLF) {
+            input = rest;
+        }
+        split_lines(input).enumerate().find_map(|(i, line)| {
+            line.iter()
+                .position(|&b| b == b'\n')
+                .map(|pos| ParseError::InvalidLineEnding {
+                    line: i + 1,
+                    column: pos + 1,
+                })
+        })
+    }
+
+    /// Splits on CRLF. The last line may lack one when the input is cut short.
+    fn split_lines(input: &[u8]) -> impl Iterator<Item = &[u8]> {
+        let mut rest = input;
+        std::iter::from_fn(move || {
+            if rest.is_empty() {
+                return None;
+            }
+            let (line, next) = match rest.windows(CRLF.len()).position(|window| window == CRLF) {
+                Some(pos) => (&rest[..pos], &rest[pos + CRLF.len()..]),
+                None => (rest, &rest[rest.len()..]),
+            };
+            rest = next;
+            Some(line)
+        })
+    }
+
+    fn check_line_ending(number: usize, line: &[u8]) -> Result<(), ParseError> {
+        match line.iter().position(|&b| b == b'\r' || b == b'\n') {
+            Some(pos) => Err(ParseError::InvalidLineEnding {
+                line: number,
+                column: pos + 1,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// `method SP request-target SP HTTP-version`, the version being 1.0 or 1.1.
+    fn parse_request_line(line: &[u8]) -> Result<(HttpMethod, String, String), ParseError> {
+        let parts: Vec<&[u8]> = line.split(|&b| b == b' ').collect();
+        let (method, target, version) = match parts[..] {
+            [method, target, version] => (method, target, version),
+            _ => {
+                return Err(ParseError::InvalidRequestLine(
+                    String::from_utf8_lossy(line).into_owned(),
+                ))
+            }
+        };
+        if let Some(pos) = find_invalid(method, is_tchar) {
+            return Err(ParseError::InvalidMethod { column: pos + 1 });
+        }
+        let target_column = method.len() + 2;
+        if let Some(pos) = find_invalid(target, |b| b.is_ascii_graphic()) {
+            return Err(ParseError::InvalidTarget {
+                column: target_column + pos,
+            });
+        }
+
+        let version = String::from_utf8_lossy(version).into_owned();
+        let is_http_version = matches!(
+            version.as_bytes(),
+            [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+                if major.is_ascii_digit() && minor.is_ascii_digit()
+        );
+        if !is_http_version {
+            return Err(ParseError::InvalidVersion(version));
+        }
+        if version != "HTTP/1.0" && version != "HTTP/1.1" {
+            return Err(ParseError::UnsupportedVersion(version));
+        }
+
+        // Both are plain ASCII by now.
+        let method = HttpMethod::from_string(&String::from_utf8_lossy(method));
+        let target = String::from_utf8_lossy(target).into_owned();
+        Ok((method, target, version))
+    }
+
+    /// `field-name ":" OWS field-value OWS`. Values may hold any visible character,
+    /// spaces, tabs and obs-text; the latter is decoded lossily. Trailer fields follow
+    /// the same rules, `number` then counting from the start of the trailer section.
+    pub(crate) fn parse_header(number: usize, line: &[u8]) -> Result<(String, String), ParseError> {
+        check_line_ending(number, line)?;
+        if line.starts_with(b" ") || line.starts_with(b"\t") {
+            return Err(ParseError::ObsoleteLineFolding { line: number });
+        }
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or(ParseError::MissingColon { line: number })?;
+        let name = &line[..colon];
+        if name.ends_with(b" ") || name.ends_with(b"\t") {
+            return Err(ParseError::WhitespaceBeforeColon { line: number });
+        }
+        if let Some(pos) = find_invalid(name, is_tchar) {
+            return Err(ParseError::InvalidHeaderName {
+                line: number,
+                column: pos + 1,
+            });
+        }
+
+        let value = &line[colon + 1..];
+        if let Some(pos) = value
+            .iter()
+            .position(|&b| (b.is_ascii_control() && b != b'\t') || b == 0x7f)
+        {
+            return Err(ParseError::InvalidHeaderValue {
+                line: number,
+                column: colon + pos + 2,
+            });
+        }
+        let value = value.trim_ascii();
+        Ok((
+            String::from_utf8_lossy(name).into_owned(),
+            String::from_utf8_lossy(value).into_owned(),
+        ))
+    }
+
+    /// A body is delimited by either `Content-Length` or `Transfer-Encoding`, never
+    /// both: an intermediary honouring the other one would see a different request.
+    fn check_framing(headers: &HeaderMap) -> Result<(), ParseError> {
+        if headers.contains("Content-Length") && headers.contains("Transfer-Encoding") {
+            return Err(ParseError::ContentLengthWithTransferEncoding);
+        }
+        headers.get_content_length()?;
+        Ok(())
+    }
+
+    /// The offset of the first byte failing `is_valid`, or 0 if there are no bytes at all.
+    fn find_invalid(bytes: &[u8], is_valid: impl Fn(u8) -> bool) -> Option<usize> {
+        if bytes.is_empty() {
+            return Some(0);
+        }
+        bytes.iter().position(|&b| !is_valid(b))
+    }
+
+    /// Returns the offset just past the empty line that ends the header section.
+    pub fn find_header_end(input: &[u8]) -> Option<usize> {
+        input
+            .windows(HEADER_TERMINATOR.len())
+            .position(|window| window == HEADER_TERMINATOR)
+            .map(|pos| pos + HEADER_TERMINATOR.len())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::http::Status;
+
+        fn parse(head: &str) -> Result<Request, ParseError> {
+            Request::from_head(head.as_bytes())
+        }
+
+        #[test]
+        fn parses_request_line_and_headers() {
+            let req = parse(
+                "GET /echo/abc?x=1 HTTP/1.1\r\nHost: localhost\r\nUser-Agent:  curl/8.0 \r\n\r\n",
+            )
+            .unwrap();
+            assert_eq!(*req.get_method(), HttpMethod::Get);
+            assert_eq!(req.get_path(), "/echo/abc");
+            assert_eq!(req.get_query(), Some("x=1"));
+            assert_eq!(req.get_http_version(), "HTTP/1.1");
+            assert_eq!(req.get_header("host"), Some("localhost"));
+            assert_eq!(req.get_header("User-Agent"), Some("curl/8.0"));
+        }
+
+        #[test]
+        fn skips_empty_lines_before_the_request_line() {
+            let req = parse("\r\n\r\nGET / HTTP/1.0\r\n\r\n").unwrap();
+            assert_eq!(req.get_path(), "/");
+        }
+
+        #[test]
+        fn rejects_malformed_request_lines() {
+            assert_eq!(parse("").unwrap_err(), ParseError::EmptyRequest);
+            assert_eq!(
+                parse("GET  / HTTP/1.1\r\n\r\n").unwrap_err(),
+                ParseError::InvalidRequestLine("GET  / HTTP/1.1".to_string())
+            );
+            assert_eq!(
+                parse("G(T / HTTP/1.1\r\n\r\n").unwrap_err(),
+                ParseError::InvalidMethod { column: 2 }
+            );
+            assert_eq!(
+                parse("GET /a\x7fb HTTP/1.1\r\n\r\n").unwrap_err(),
+                ParseError::InvalidTarget { column: 7 }
+            );
+            assert_eq!(
+                parse("GET / HTTP/1\r\n\r\n").unwrap_err(),
+                ParseError::InvalidVersion("HTTP/1".to_string())
+            );
+            let err = parse("GET / HTTP/2.0\r\n\r\n").unwrap_err();
+            assert_eq!(err, ParseError::UnsupportedVersion("HTTP/2.0".to_string()));
+            assert_eq!(err.get_status(), Status::HttpVersionNotSupported);
+        }
+
+        #[test]
+        fn rejects_bare_line_endings() {
+            assert_eq!(
+                parse("GET / HTTP/1.1\r\nHost: a\rb\r\n\r\n").unwrap_err(),
+                ParseError::InvalidLineEnding { line: 2, column: 8 }
+            );
+            assert_eq!(
+                find_bare_lf(b"GET / HTTP/1.1\nHost: x\n\n"),
+                Some(ParseError::InvalidLineEnding {
+                    line: 1,
+                    column: 15
+                })
+            );
+            assert_eq!(find_bare_lf(b"\r\nGET / HTTP/1.1\r\nHost: x\r"), None);
+        }
+
+        #[test]
+        fn rejects_malformed_headers() {
+            assert_eq!(
+                parse("GET / HTTP/1.1\r\nHost: x\r\nX: a\r\n  b\r\n\r\n").unwrap_err(),
+                ParseError::ObsoleteLineFolding { line: 4 }
+            );
+            assert_eq!(
+                parse("GET / HTTP/1.1\r\nHost x\r\n\r\n").unwrap_err(),
+                ParseError::MissingColon { line: 2 }
+            );
+            assert_eq!(
+                parse("GET / HTTP/1.1\r\nHost : x\r\n\r\n").unwrap_err(),
+                ParseError::WhitespaceBeforeColon { line: 2 }
+            );
+            assert_eq!(
+                parse("GET / HTTP/1.1\r\nHo(st: x\r\n\r\n").unwrap_err(),
+                ParseError::InvalidHeaderName { line: 2, column: 3 }
+            );
+            assert_eq!(
+                parse("GET / HTTP/1.1\r\nHost: x\x01y\r\n\r\n").unwrap_err(),
+                ParseError::InvalidHeaderValue { line: 2, column: 8 }
+            );
+        }
+
+        #[test]
+        fn requires_exactly_one_host_in_http_1_1() {
+            assert_eq!(
+                parse("GET / HTTP/1.1\r\n\r\n").unwrap_err(),
+                ParseError::MissingHost
+            );
+            assert!(parse("GET / HTTP/1.0\r\n\r\n").is_ok());
+            assert_eq!(
+                parse("GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n").unwrap_err(),
+                ParseError::RepeatedHost
+            );
+        }
+
+        #[test]
+        fn checks_body_framing() {
+            assert_eq!(
+                parse("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n")
+                    .unwrap_err(),
+                ParseError::ContentLengthWithTransferEncoding
+            );
+            assert_eq!(
+                parse("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: +3\r\n\r\n").unwrap_err(),
+                ParseError::InvalidContentLength("+3".to_string())
+            );
+            assert_eq!(
+                parse("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 3, 4\r\n\r\n").unwrap_err(),
+                ParseError::InvalidContentLength("4".to_string())
+            );
+            let req = parse("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 3, 3\r\n\r\n").unwrap();
+            assert_eq!(req.get_headers().get_content_length(), Ok(Some(3)));
+        }
+    }
+}
+
+pub mod uri {
+
+    use super::{HttpMethod, ParseError};
+
+    /// How the request target was written, see RFC 9112 section 3.2.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum TargetForm {
+        /// `/path?query`, the usual form.
+        #[default]
+        Origin,
+        /// `http://authority/path?query`, as sent to proxies.
+        Absolute,
+        /// `*`, for a server-wide OPTIONS.
+        Asterisk,
+    }
+
+    /// A request target taken apart: the path as sent and split into decoded
+    /// segments, and the query as sent and decoded into name-value pairs.
+    #[derive(Debug, Clone, Default)]
+    pub struct RequestTarget {
+        pub form: TargetForm,
+        /// Only present in absolute-form, where it overrides `Host`.
+        pub authority: Option<String>,
+        pub path: String,
+        pub segments: Vec<String>,
+        pub query: Option<String>,
+        pub query_params: Vec<(String, String)>,
+    }
+
+    impl RequestTarget {
+        pub fn parse(target: &str, method: &HttpMethod) -> Result<Self, ParseError> {
+            if target == "*" {
+                if *method != HttpMethod::Options {
+                    return Err(ParseError::InvalidTargetForm(target.to_string()));
+                }
+                return Ok(Self {
+                    form: TargetForm::Asterisk,
+                    path: target.to_string(),
+                    ..Self::default()
+                });
+            }
+
+            let (form, authority, rest) = match split_scheme(target) {
+                Some(rest) => {
+                    let rest = rest
+                        .strip_prefix("//")
+                        .ok_or_else(|| ParseError::InvalidTargetForm(target.to_string()))?;
+                    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+                    if end == 0 {
+                        return Err(ParseError::InvalidTargetForm(target.to_string()));
+                    }
+                    (
+                        TargetForm::Absolute,
+                        Some(rest[..end].to_string()),
+                        &rest[end..],
+                    )
+                }
+                None if target.starts_with('/') => (TargetForm::Origin, None, target),
+                None => return Err(ParseError::InvalidTargetForm(target.to_string())),
+            };
+            let (path, query) = match rest.split_once('?') {
+                Some((path, query)) => (path, Some(query)),
+                None => (rest, None),
+            };
+            // An absolute-form target may leave the path out entirely.
+            let path = if path.is_empty() { "/" } else { path };
+
+            Ok(Self {
+                form,
+                authority,
+                path: path.to_string(),
+                segments: split_path(path)
+                    .into_iter()
+                    .map(percent_decode)
+                    .collect::<Result<Vec<String>, ParseError>>()?,
+                query: query.map(str::to_string),
+                query_params: query.map(parse_query).transpose()?.unwrap_or_default(),
+            })
+        }
+    }
+
+    /// The rest of an `http:` or `https:` URI, after the scheme.
+    fn split_scheme(target: &str) -> Option<&str> {
+        let (scheme, rest) = target.split_once(':')?;
+        (scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+            .then_some(rest)
+    }
+
+    /// `/` is no segments at all, `/echo/` is `echo` followed by an empty segment.
+    pub fn split_path(path: &str) -> Vec<&str> {
+        match path.strip_prefix('/').unwrap_or(path) {
+            "" => Vec::new(),
+            path => path.split('/').collect(),
+        }
+    }
+
+    /// Decodes `name=value` pairs separated by `&`, with `+` standing for a space as
+    /// in HTML forms. A pair without `=` has an empty value.
+    pub fn parse_query(query: &str) -> Result<Vec<(String, String)>, ParseError> {
+        query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((
+                    percent_decode(&name.replace('+', " "))?,
+                    percent_decode(&value.replace('+', " "))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Decodes `%XX` escapes. The decoded bytes have to form valid UTF-8.
+    pub fn percent_decode(input: &str) -> Result<String, ParseError> {
+        let bytes = input.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'%' {
+                let byte = bytes
+                    .get(i + 1..i + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+                    .ok_or_else(|| ParseError::InvalidPercentEncoding(input.to_string()))?;
+                decoded.push(byte);
+                i += 3;
+            } else {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+        String::from_utf8(decoded)
+            .map_err(|_| ParseError::InvalidPercentEncoding(input.to_string()))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn parse(target: &str) -> Result<RequestTarget, ParseError> {
+            RequestTarget::parse(target, &HttpMethod::Get)
+        }
+
+        #[test]
+        fn parses_origin_form() {
+            let target = parse("/files/a%20b/?q=1+2&flag&name=%C3%A9").unwrap();
+            assert_eq!(target.form, TargetForm::Origin);
+            assert_eq!(target.authority, None);
+            assert_eq!(target.path, "/files/a%20b/");
+            assert_eq!(target.segments, ["files", "a b", ""]);
+            assert_eq!(target.query.as_deref(), Some("q=1+2&flag&name=%C3%A9"));
+            assert_eq!(
+                target.query_params,
+                [
+                    ("q".to_string(), "1 2".to_string()),
+                    ("flag".to_string(), String::new()),
+                    ("name".to_string(), "é".to_string()),
+                ]
+            );
+            assert!(parse("/").unwrap().segments.is_empty());
+        }
+
+        #[test]
+        fn parses_absolute_form() {
+            let target = parse("HTTP://example.com:8080/echo/hi?x=y").unwrap();
+            assert_eq!(target.form, TargetForm::Absolute);
+            assert_eq!(target.authority.as_deref(), Some("example.com:8080"));
+            assert_eq!(target.path, "/echo/hi");
+            assert_eq!(target.segments, ["echo", "hi"]);
+
+            let target = parse("https://example.com?x").unwrap();
+            assert_eq!(target.path, "/");
+            assert_eq!(target.query.as_deref(), Some("x"));
+        }
+
+        #[test]
+        fn allows_asterisk_only_for_options() {
+            let target = RequestTarget::parse("*", &HttpMethod::Options).unwrap();
+            assert_eq!(target.form, TargetForm::Asterisk);
+            assert_eq!(target.path, "*");
+            assert_eq!(
+                parse("*").unwrap_err(),
+                ParseError::InvalidTargetForm("*".to_string())
+            );
+        }
+
+        #[test]
+        fn rejects_other_target_forms() {
+            for target in [
+                "echo",
+                "example.com:80",
+                "ftp://example.com/",
+                "http:/x",
+                "http:///x",
+            ] {
+                assert_eq!(
+                    parse(target).unwrap_err(),
+                    ParseError::InvalidTargetForm(target.to_string())
+                );
+            }
+        }
+
+        #[test]
+        fn rejects_bad_percent_encoding() {
+            assert_eq!(
+                parse("/a%ZZ/b").unwrap_err(),
+                ParseError::InvalidPercentEncoding("a%ZZ".to_string())
+            );
+            assert_eq!(
+                parse("/a%2").unwrap_err(),
+                ParseError::InvalidPercentEncoding("a%2".to_string())
+            );
+            assert_eq!(
+                parse("/%FF").unwrap_err(),
+                ParseError::InvalidPercentEncoding("%FF".to_string())
+            );
+            assert_eq!(
+                parse("/?x=%G0").unwrap_err(),
+                ParseError::InvalidPercentEncoding("%G0".to_string())
+            );
+        }
+    }
+}
+
+pub mod chunked {
+
+    use std::io::{self, Write};
+
+    use bytes::{Buf, Bytes, BytesMut};
+
+    use super::headers::HeaderMap;
+    use super::request::parse_header;
+    use super::ParseError;
+
+    const CRLF: &[u8] = b"\r\n";
+    const MAX_LINE_SIZE: usize = 4 * 1024;
+    const MAX_TRAILERS_SIZE: usize = 8 * 1024;
+
+    #[derive(Debug, Default)]
+    enum DecoderState {
+        #[default]
+        Size,
+        Data(usize),
+        DataEnd,
+        Trailers,
+        Done,
+    }
+
+    /// Incrementally decodes a body in the chunked transfer coding, consuming
+    /// input as it arrives so a request never has to be buffered twice.
+    #[derive(Debug, Default)]
+    pub struct ChunkedDecoder {
+        state: DecoderState,
+        body: BytesMut,
+        trailers: HeaderMap,
+        trailers_size: usize,
+    }
+
+    impl ChunkedDecoder {
+        /// Consumes as much of `buf` as possible. Returns `Ok(true)` once the last chunk
+        /// and the trailer section have been read; any bytes after them are left in `buf`.
+        pub fn decode(&mut self, buf: &mut BytesMut) -> Result<bool, ParseError> {
+            loop {
+                match self.state {
+                    DecoderState::Size => {
+                        let line = match take_line(buf)? {
+                            Some(line) => String::from_utf8_lossy(&line).into_owned(),
+                            None => return Ok(false),
+                        };
+                        // Chunk extensions are allowed after the size and ignored.
+                        let size = line.split(';').next().unwrap_or_default().trim();
+                        if size.is_empty() || !size.chars().all(|c| c.is_ascii_hexdigit()) {
+                            return Err(ParseError::InvalidChunkSize(line));
+                        }
+                        let size = usize::from_str_radix(size, 16)
+                            .map_err(|_| ParseError::InvalidChunkSize(size.to_string()))?;
+                        self.state = if size == 0 {
+                            DecoderState::Trailers
+                        } else {
+                            DecoderState::Data(size)
+                        };
+                    }
+                    DecoderState::Data(remaining) => {
+                        if buf.is_empty() {
+                            return Ok(false);
+                        }
+                        let available = remaining.min(buf.len());
+                        self.body.extend_from_slice(&buf.split_to(available));
+                        self.state = if available == remaining {
+                            DecoderState::DataEnd
+                        } else {
+                            DecoderState::Data(remaining - available)
+                        };
+                    }
+                    DecoderState::DataEnd => {
+                        if buf.len() < CRLF.len() {
+                            return Ok(false);
+                        }
+                        if &buf[..CRLF.len()] != CRLF {
+                            return Err(ParseError::MissingChunkTerminator);
+                        }
+                        buf.advance(CRLF.len());
+                        self.state = DecoderState::Size;
+                    }
+                    DecoderState::Trailers => {
+                        let line = match take_line(buf)? {
+                            Some(line) => line,
+                            None => return Ok(false),
+                        };
+                        if line.is_empty() {
+                            self.state = DecoderState::Done;
+                            continue;
+                        }
+                        self.trailers_size += line.len() + CRLF.len();
+                        if self.trailers_size > MAX_TRAILERS_SIZE {
+                            return Err(ParseError::TrailersTooLarge(MAX_TRAILERS_SIZE));
+                        }
+                        let (name, value) = parse_header(self.trailers.len() + 1, &line)
+                            .map_err(|err| ParseError::InvalidTrailer(err.to_string()))?;
+                        self.trailers.append(name, value);
+                    }
+                    DecoderState::Done => return Ok(true),
+                }
+            }
+        }
+
+        /// Number of body bytes decoded so far.
+        pub fn body_len(&self) -> usize {
+            self.body.len()
+        }
+
+        pub fn finish(self) -> (Bytes, HeaderMap) {
+            (self.body.freeze(), self.trailers)
+        }
+    }
+
+    fn take_line(buf: &mut BytesMut) -> Result<Option<BytesMut>, ParseError> {
+        match buf.windows(CRLF.len()).position(|window| window == CRLF) {
+            Some(pos) => {
+                let line = buf.split_to(pos);
+                buf.advance(CRLF.len());
+                Ok(Some(line))
+            }
+            None if buf.len() > MAX_LINE_SIZE => Err(ParseError::ChunkLineTooLong(MAX_LINE_SIZE)),
+            None => Ok(None),
+        }
+    }
+
+    /// Frames everything written through it as chunks. `finish` must be called
+    /// to send the terminating zero-length chunk.
+    pub struct ChunkedWriter<W: Write> {
+        inner: W,
+    }
+
+    impl<W: Write> ChunkedWriter<W> {
+        pub fn new(inner: W) -> Self {
+            Self { inner }
+        }
+
+        pub fn finish(mut self) -> io::Result<W> {
+            self.inner.write_all(b"0\r\n\r\n")?;
+            Ok(self.inner)
+        }
+    }
+
+    impl<W: Write> Write for ChunkedWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            // An empty chunk would terminate the body.
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            write!(self.inner, "{:X}\r\n", buf.len())?;
+            self.inner.write_all(buf)?;
+            self.inner.write_all(CRLF)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Feeds `input` to a decoder `step` bytes at a time, as reads would deliver it.
+        fn decode(input: &[u8], step: usize) -> Result<(Bytes, HeaderMap, BytesMut), ParseError> {
+            let mut decoder = ChunkedDecoder::default();
+            let mut buf = BytesMut::new();
+            for piece in input.chunks(step) {
+                buf.extend_from_slice(piece);
+                if decoder.decode(&mut buf)? {
+                    let (body, trailers) = decoder.finish();
+                    return Ok((body, trailers, buf));
+                }
+            }
+            Err(ParseError::IncompleteBody)
+        }
+
+        #[test]
+        fn decodes_chunks_with_extensions() {
+            let input = b"4\r\nWiki\r\n5;name=\"a;b\";flag\r\npedia\r\nE \r\n in\r\n\r\nchunks.\r\n0\r\n\r\nGET";
+            for step in [input.len(), 7, 1] {
+                let (body, trailers, rest) = decode(input, step).unwrap();
+                assert_eq!(&body[..], b"Wikipedia in\r\n\r\nchunks.");
+                assert!(trailers.is_empty());
+                // Whatever had arrived after the last chunk is left for the next request.
+                assert!(b"GET".starts_with(&rest));
+            }
+        }
+
+        #[test]
+        fn decodes_trailers() {
+            let input = b"3\r\nabc\r\n0\r\nExpires: never\r\nX-Sum:  abc \r\n\r\n";
+            for step in [input.len(), 1] {
+                let (body, trailers, _) = decode(input, step).unwrap();
+                assert_eq!(&body[..], b"abc");
+                assert_eq!(trailers.get("expires"), Some("never"));
+                assert_eq!(trailers.get("X-Sum"), Some("abc"));
+            }
+        }
+
+        #[test]
+        fn checks_trailers_like_headers() {
+            let cases = [
+                (
+                    "0\r\nBad Name: x\r\n\r\n",
+                    ParseError::InvalidHeaderName { line: 1, column: 4 },
+                ),
+                (
+                    "0\r\nX-Sum : x\r\n\r\n",
+                    ParseError::WhitespaceBeforeColon { line: 1 },
+                ),
+                (
+                    "0\r\nA: 1\r\nnocolon\r\n\r\n",
+                    ParseError::MissingColon { line: 2 },
+                ),
+                (
+                    "0\r\nA: 1\r\n folded\r\n\r\n",
+                    ParseError::ObsoleteLineFolding { line: 2 },
+                ),
+                (
+                    "0\r\nA: x\x01y\r\n\r\n",
+                    ParseError::InvalidHeaderValue { line: 1, column: 5 },
+                ),
+                (
+                    "0\r\nA: x\ny\r\n\r\n",
+                    ParseError::InvalidLineEnding { line: 1, column: 5 },
+                ),
+            ];
+            for (input, err) in cases {
+                assert_eq!(
+                    decode(input.as_bytes(), input.len()).unwrap_err(),
+                    ParseError::InvalidTrailer(err.to_string()),
+                    "{:?}",
+                    input
+                );
+            }
+        }
+
+        #[test]
+        fn rejects_malformed_chunks() {
+            assert_eq!(
+                decode(b"zz\r\nabc\r\n", 64).unwrap_err(),
+                ParseError::InvalidChunkSize("zz".to_string())
+            );
+            assert_eq!(
+                decode(b"\r\n", 64).unwrap_err(),
+                ParseError::InvalidChunkSize(String::new())
+            );
+            assert_eq!(
+                decode(b"3\r\nabcd\r\n0\r\n\r\n", 1).unwrap_err(),
+                ParseError::MissingChunkTerminator
+            );
+            assert_eq!(
+                decode(b"3\r\nab", 64).unwrap_err(),
+                ParseError::IncompleteBody
+            );
+        }
+
+        #[test]
+        fn bounds_lines_and_trailers() {
+            let mut long_line = b"1;".to_vec();
+            long_line.resize(MAX_LINE_SIZE + 1, b'x');
+            assert_eq!(
+                decode(&long_line, 256).unwrap_err(),
+                ParseError::ChunkLineTooLong(MAX_LINE_SIZE)
+            );
+
+            let mut trailers = b"0\r\n".to_vec();
+            let field = format!("X-Pad: {}\r\n", "a".repeat(100));
+            while trailers.len() <= MAX_TRAILERS_SIZE + field.len() {
+                trailers.extend_from_slice(field.as_bytes());
+            }
+            trailers.extend_from_slice(CRLF);
+            assert_eq!(
+                decode(&trailers, 512).unwrap_err(),
+                ParseError::TrailersTooLarge(MAX_TRAILERS_SIZE)
+            );
+        }
+
+        #[test]
+        fn writes_what_it_decodes() {
+            let mut writer = ChunkedWriter::new(Vec::new());
+            writer.write_all(b"hello ").unwrap();
+            writer.write_all(b"").unwrap();
+            writer.write_all(b"world").unwrap();
+            let encoded = writer.finish().unwrap();
+            assert_eq!(&encoded[..], b"6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n");
+            let (body, _, rest) = decode(&encoded, 1).unwrap();
+            assert_eq!(&body[..], b"hello world");
+            assert!(rest.is_empty());
+        }
+    }
+}
+
+pub mod reader {
+
+    use std::io::{self, Read};
+    use std::time::{Duration, Instant};
+
+    use bytes::{Buf, BytesMut};
+    use thiserror::Error;
+
+    use super::chunked::ChunkedDecoder;
+    use super::request::{self, Request, HEADER_TERMINATOR};
+    use super::{ParseError, Status};
+
+    pub const DEFAULT_MAX_HEADER_SIZE: usize = 8 * 1024;
+    pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+    pub const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+    pub const DEFAULT_BODY_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// Upper bounds on how much of a request the reader is willing to buffer, and
+    /// for how long.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Limits {
+        pub max_header_size: usize,
+        pub max_body_size: usize,
+        /// Time from the first byte of a request to the end of its header section,
+        /// however steadily the bytes trickle in.
+        pub header_timeout: Duration,
+        /// Time from the end of the header section to the end of the body.
+        pub body_timeout: Duration,
+    }
+
+    impl Default for Limits {
+        fn default() -> Self {
+            Self {
+                max_header_size: DEFAULT_MAX_HEADER_SIZE,
+                max_body_size: DEFAULT_MAX_BODY_SIZE,
+                header_timeout: DEFAULT_HEADER_TIMEOUT,
+                body_timeout: DEFAULT_BODY_TIMEOUT,
+            }
+        }
+    }
+
+    #[derive(Debug, Error)]
+    pub enum ReadError {
+        #[error("request header section exceeds {0} bytes")]
+        HeadersTooLarge(usize),
+        #[error("request header section not received in time")]
+        HeaderTimeout,
+        #[error("request body not received in time")]
+        BodyTimeout,
+        #[error("request body of {0} bytes exceeds the limit of {1} bytes")]
+        BodyTooLarge(usize, usize),
+        #[error("connection closed before the request was complete")]
+        UnexpectedEof,
+        #[error("malformed request: {0}")]
+        Malformed(#[from] ParseError),
+        #[error("unsupported transfer coding: {0}")]
+        UnsupportedTransferCoding(String),
+        #[error(transparent)]
+        Io(#[from] io::Error),
+    }
 
-    use super::HttpMethod;
+    impl ReadError {
+        /// The status to answer with, or `None` if the peer can no longer be answered.
+        pub fn get_status(&self) -> Option<Status> {
+            match self {
+                Self::HeadersTooLarge(_) => Some(Status::RequestHeaderFieldsTooLarge),
+                Self::HeaderTimeout | Self::BodyTimeout => Some(Status::RequestTimeout),
+                Self::BodyTooLarge(_, _) => Some(Status::ContentTooLarge),
+                Self::Malformed(err) => Some(err.get_status()),
+                Self::UnsupportedTransferCoding(_) => Some(Status::NotImplemented),
+                Self::UnexpectedEof | Self::Io(_) => None,
+            }
+        }
+    }
 
-    #[derive(Debug, Default)]
-    pub struct Request {
-        method: HttpMethod,
-        path: String,
-        http_version: String,
-        headers: HashMap<String, String>,
-        body: Option<String>,
+    /// Reads requests from a stream `N` bytes at a time, buffering until the header
+    /// section and the body, delimited by `Content-Length` or chunked, are complete.
+    pub struct RequestReader<R: Read, const N: usize> {
+        stream: R,
+        buf: BytesMut,
+        limits: Limits,
     }
 
-    impl Request {
-        pub fn get_method(&'_ self) -> &'_ HttpMethod {
-            &self.method
+    impl<R: Read, const N: usize> RequestReader<R, N> {
+        pub fn new(stream: R, limits: Limits) -> Self {
+            Self {
+                stream,
+                buf: BytesMut::with_capacity(N),
+                limits,
+            }
         }
 
-        pub fn get_path(&'_ self) -> &'_ str {
-            &self.path
+        /// Whether part of a next request has already been read off the stream.
+        pub fn has_buffered_data(&self) -> bool {
+            !self.buf.is_empty()
         }
 
-        pub fn get_http_version(&'_ self) -> &'_ str {
-            &self.http_version
+        /// Returns `Ok(None)` if the peer closed the connection, or let the read timeout
+        /// elapse, without sending anything.
+        pub fn read_request(&mut self) -> Result<Option<Request>, ReadError> {
+            let head_len = match self.read_head()? {
+                Some(head_len) => head_len,
+                None => return Ok(None),
+            };
+            let mut request = Request::from_head(&self.buf[..head_len])?;
+            check_transfer_codings(&request)?;
+            let deadline = Instant::now() + self.limits.body_timeout;
+            if request.is_chunked() {
+                self.buf.advance(head_len);
+                self.read_chunked_body(&mut request, deadline)?;
+                return Ok(Some(request));
+            }
+
+            let content_length = get_content_length(&request)?;
+            if content_length > self.limits.max_body_size {
+                return Err(ReadError::BodyTooLarge(
+                    content_length,
+                    self.limits.max_body_size,
+                ));
+            }
+
+            let request_len = head_len + content_length;
+            while self.buf.len() < request_len {
+                self.fill_body(deadline)?;
+            }
+            let mut raw = self.buf.split_to(request_len);
+            request.set_body(raw.split_off(head_len).freeze());
+            Ok(Some(request))
         }
 
-        pub fn get_headers(&'_ self) -> &'_ HashMap<String, String> {
-            &self.headers
+        fn read_chunked_body(
+            &mut self,
+            request: &mut Request,
+            deadline: Instant,
+        ) -> Result<(), ReadError> {
+            let mut decoder = ChunkedDecoder::default();
+            while !decoder.decode(&mut self.buf)? {
+                if decoder.body_len() > self.limits.max_body_size {
+                    return Err(ReadError::BodyTooLarge(
+                        decoder.body_len(),
+                        self.limits.max_body_size,
+                    ));
+                }
+                self.fill_body(deadline)?;
+            }
+            if decoder.body_len() > self.limits.max_body_size {
+                return Err(ReadError::BodyTooLarge(
+                    decoder.body_len(),
+                    self.limits.max_body_size,
+                ));
+            }
+            let (body, trailers) = decoder.finish();
+            request.set_body(body);
+            request.set_trailers(trailers);
+            Ok(())
         }
 
-        pub fn get_body(&'_ self) -> &'_ Option<String> {
-            &self.body
+        /// Buffers input until the empty line ending the header section and returns its offset.
+        /// A bare LF fails as soon as it arrives, as the section could otherwise never end.
+        fn read_head(&mut self) -> Result<Option<usize>, ReadError> {
+            let started = Instant::now();
+            let mut searched = 0;
+            loop {
+                if let Some(end) = request::find_header_end(&self.buf[searched..]) {
+                    let head_len = searched + end;
+                    if head_len > self.limits.max_header_size {
+                        return Err(ReadError::HeadersTooLarge(self.limits.max_header_size));
+                    }
+                    return Ok(Some(head_len));
+                }
+                if let Some(err) = request::find_bare_lf(&self.buf) {
+                    return Err(err.into());
+                }
+                if self.buf.len() > self.limits.max_header_size {
+                    return Err(ReadError::HeadersTooLarge(self.limits.max_header_size));
+                }
+                if !self.buf.is_empty() && started.elapsed() >= self.limits.header_timeout {
+                    return Err(ReadError::HeaderTimeout);
+                }
+                // The terminator may straddle two reads.
+                searched = self.buf.len().saturating_sub(HEADER_TERMINATOR.len() - 1);
+                let bytes_read = match self.fill() {
+                    Err(err) if is_timeout(&err) && self.buf.is_empty() => 0,
+                    Err(err) if is_timeout(&err) => return Err(ReadError::HeaderTimeout),
+                    result => result?,
+                };
+                if bytes_read == 0 {
+                    return if self.buf.is_empty() {
+                        Ok(None)
+                    } else {
+                        Err(ReadError::UnexpectedEof)
+                    };
+                }
+            }
+        }
+
+        /// Reads more of a body that has to be complete by `deadline`, so a client
+        /// can't hold the connection by sending a byte now and then.
+        fn fill_body(&mut self, deadline: Instant) -> Result<(), ReadError> {
+            if Instant::now() >= deadline {
+                return Err(ReadError::BodyTimeout);
+            }
+            match self.fill() {
+                Ok(0) => Err(ReadError::UnexpectedEof),
+                Ok(_) => Ok(()),
+                Err(err) if is_timeout(&err) => Err(ReadError::BodyTimeout),
+                Err(err) => Err(err.into()),
+            }
+        }
+
+        fn fill(&mut self) -> io::Result<usize> {
+            let mut chunk: [u8; N] = [0; N];
+            let bytes_read = self.stream.read(&mut chunk[..])?;
+            self.buf.extend_from_slice(&chunk[..bytes_read]);
+            Ok(bytes_read)
+        }
+    }
+
+    fn is_timeout(err: &io::Error) -> bool {
+        matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    }
+
+    /// Chunked is the only transfer coding understood, and it has to come last
+    /// for the body length to be known.
+    fn check_transfer_codings(head: &Request) -> Result<(), ReadError> {
+        let codings = head.get_transfer_codings();
+        if let Some(coding) = codings
+            .iter()
+            .find(|coding| !coding.eq_ignore_ascii_case("chunked"))
+        {
+            return Err(ReadError::UnsupportedTransferCoding(coding.to_string()));
         }
+        if codings.len() > 1 {
+            return Err(ParseError::RepeatedChunked.into());
+        }
+        Ok(())
+    }
+
+    fn get_content_length(head: &Request) -> Result<usize, ReadError> {
+        let content_length = head.get_headers().get_content_length()?.unwrap_or(0);
+        usize::try_from(content_length)
+            .map_err(|_| ParseError::InvalidContentLength(content_length.to_string()).into())
+    }
 
-        pub fn from_raw(input: &[u8]) -> Result<Self, String> {
-            let raw = String::from_utf8_lossy(&input).into_owned();
-            let lines: Vec<&str> = raw.split("\r\n").collect();
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-            // Parse request line
-            let request_line = lines.first().ok_or("Invalid request: request is empty")?;
-            let parts: Vec<&str> = request_line.split_whitespace().collect();
-            if parts.len() != 3 {
-                return Err("Malformed request: Invalid request line: {}".to_string());
+        /// Hands out `data` one byte per read, then ends or fails with `then`.
+        struct Trickle {
+            data: Vec<u8>,
+            pos: usize,
+            then: Option<io::ErrorKind>,
+        }
+
+        impl Trickle {
+            fn new(data: impl Into<Vec<u8>>) -> Self {
+                Self {
+                    data: data.into(),
+                    pos: 0,
+                    then: None,
+                }
             }
 
-            let method: HttpMethod = HttpMethod::from_string(parts[0]);
-            let path: &str = parts[1];
-            let http_version: &str = parts[2];
-            // Parse headers
-            let mut headers = HashMap::new();
-            let mut body_start = 0;
-            for (i, line) in lines.iter().enumerate().skip(1) {
-                if line.is_empty() {
-                    body_start = i + 1;
-                    break;
+            fn then_fail(self, kind: io::ErrorKind) -> Self {
+                Self {
+                    then: Some(kind),
+                    ..self
                 }
-                match line.split_once(": ") {
-                    Some((key, value)) => {
-                        headers.insert(key.to_owned(), value.to_owned());
+            }
+        }
+
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.data.get(self.pos) {
+                    Some(&b) if !buf.is_empty() => {
+                        buf[0] = b;
+                        self.pos += 1;
+                        Ok(1)
                     }
-                    _ => return Err(format!("Malformed header: {}", line)),
+                    Some(_) => Ok(0),
+                    None => match self.then {
+                        Some(kind) => Err(kind.into()),
+                        None => Ok(0),
+                    },
                 }
             }
-            // Parse body
-            let body = if body_start < lines.len() {
-                Some(lines[body_start..].join("\r\n"))
-            } else {
-                None
+        }
+
+        fn trickle_reader(stream: Trickle, limits: Limits) -> RequestReader<Trickle, 16> {
+            RequestReader::new(stream, limits)
+        }
+
+        #[test]
+        fn reads_a_head_arriving_byte_by_byte() {
+            let mut reader = trickle_reader(
+                Trickle::new("GET /echo/abc HTTP/1.1\r\nHost: x\r\n\r\n"),
+                Limits::default(),
+            );
+            let req = reader.read_request().unwrap().unwrap();
+            assert_eq!(req.get_path(), "/echo/abc");
+            assert!(req.get_body().is_empty());
+            assert!(!reader.has_buffered_data());
+            assert!(reader.read_request().unwrap().is_none());
+        }
+
+        #[test]
+        fn leaves_a_pipelined_request_buffered() {
+            let input = "POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhelloGET /b HTTP/1.1\r\nHost: x\r\n\r\n";
+            let mut reader = RequestReader::<_, 64>::new(input.as_bytes(), Limits::default());
+            let first = reader.read_request().unwrap().unwrap();
+            assert_eq!(first.get_path(), "/a");
+            assert_eq!(&first.get_body()[..], b"hello");
+            assert!(reader.has_buffered_data());
+            let second = reader.read_request().unwrap().unwrap();
+            assert_eq!(second.get_path(), "/b");
+            assert!(!reader.has_buffered_data());
+        }
+
+        #[test]
+        fn reads_a_chunked_body_with_trailers() {
+            let mut reader = trickle_reader(
+                Trickle::new("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nX-Sum: 1\r\n\r\n"),
+                Limits::default(),
+            );
+            let req = reader.read_request().unwrap().unwrap();
+            assert_eq!(&req.get_body()[..], b"hello");
+            assert_eq!(req.get_trailers().get("X-Sum"), Some("1"));
+        }
+
+        #[test]
+        fn limits_the_header_section() {
+            let limits = Limits {
+                max_header_size: 32,
+                ..Limits::default()
             };
-            Ok(Self {
-                method,
-                path: path.to_owned(),
-                http_version: http_version.to_owned(),
-                headers,
-                body,
-            })
+            let mut reader = trickle_reader(
+                Trickle::new("GET / HTTP/1.1\r\nHost: x\r\nUser-Agent: curl\r\n\r\n"),
+                limits,
+            );
+            let err = reader.read_request().unwrap_err();
+            assert!(matches!(err, ReadError::HeadersTooLarge(32)));
+            assert_eq!(err.get_status(), Some(Status::RequestHeaderFieldsTooLarge));
+        }
+
+        #[test]
+        fn limits_the_body() {
+            let limits = Limits {
+                max_body_size: 4,
+                ..Limits::default()
+            };
+            let mut reader = trickle_reader(
+                Trickle::new("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello"),
+                limits,
+            );
+            let err = reader.read_request().unwrap_err();
+            assert!(matches!(err, ReadError::BodyTooLarge(5, 4)));
+            assert_eq!(err.get_status(), Some(Status::ContentTooLarge));
+
+            let mut reader = trickle_reader(
+                Trickle::new("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n"),
+                limits,
+            );
+            assert!(matches!(
+                reader.read_request().unwrap_err(),
+                ReadError::BodyTooLarge(_, 4)
+            ));
+        }
+
+        #[test]
+        fn fails_on_eof_partway_through_a_request() {
+            let mut reader = trickle_reader(
+                Trickle::new("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhel"),
+                Limits::default(),
+            );
+            let err = reader.read_request().unwrap_err();
+            assert!(matches!(err, ReadError::UnexpectedEof));
+            assert_eq!(err.get_status(), None);
+
+            let mut reader = trickle_reader(
+                Trickle::new(
+                    "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel",
+                ),
+                Limits::default(),
+            );
+            assert!(matches!(
+                reader.read_request().unwrap_err(),
+                ReadError::UnexpectedEof
+            ));
+
+            let mut reader = trickle_reader(Trickle::new("GET / HTTP/1.1\r\n"), Limits::default());
+            assert!(matches!(
+                reader.read_request().unwrap_err(),
+                ReadError::UnexpectedEof
+            ));
+        }
+
+        #[test]
+        fn times_out_partway_through_a_request() {
+            let mut reader = trickle_reader(
+                Trickle::new("").then_fail(io::ErrorKind::WouldBlock),
+                Limits::default(),
+            );
+            assert!(reader.read_request().unwrap().is_none());
+
+            let mut reader = trickle_reader(
+                Trickle::new("GET / HTTP/1.1\r\n").then_fail(io::ErrorKind::TimedOut),
+                Limits::default(),
+            );
+            assert!(matches!(
+                reader.read_request().unwrap_err(),
+                ReadError::HeaderTimeout
+            ));
+
+            let mut reader = trickle_reader(
+                Trickle::new("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhel")
+                    .then_fail(io::ErrorKind::WouldBlock),
+                Limits::default(),
+            );
+            let err = reader.read_request().unwrap_err();
+            assert!(matches!(err, ReadError::BodyTimeout));
+            assert_eq!(err.get_status(), Some(Status::RequestTimeout));
+        }
+
+        #[test]
+        fn rejects_a_bare_lf_before_the_head_ends() {
+            let mut reader = trickle_reader(
+                Trickle::new("GET / HTTP/1.1\nHost: x\n").then_fail(io::ErrorKind::TimedOut),
+                Limits::default(),
+            );
+            assert!(matches!(
+                reader.read_request().unwrap_err(),
+                ReadError::Malformed(ParseError::InvalidLineEnding {
+                    line: 1,
+                    column: 15
+                })
+            ));
+        }
+    }
+}
+
+pub mod date {
+
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    const SECS_PER_DAY: u64 = 24 * 60 * 60;
+    /// 1970-01-01 was a Thursday.
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    /// Formats `time` as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+    pub fn format_http_date(time: SystemTime) -> String {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        let days = secs / SECS_PER_DAY;
+        let secs_of_day = secs % SECS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+        format!(
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[(days % 7) as usize],
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            secs_of_day / 3600,
+            secs_of_day % 3600 / 60,
+            secs_of_day % 60
+        )
+    }
+
+    /// Parses an IMF-fixdate. The obsolete RFC 850 and asctime formats aren't accepted,
+    /// nor are dates before 1970.
+    pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+        let fields = value.split(' ').collect::<Vec<&str>>();
+        let [weekday, day, month, year, time, "GMT"] = fields[..] else {
+            return None;
+        };
+        let weekday = weekday.strip_suffix(',')?;
+        let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+        let number = |field: &str, digits: usize| {
+            (field.len() == digits && field.bytes().all(|b| b.is_ascii_digit()))
+                .then(|| field.parse::<u64>().ok())
+                .flatten()
+        };
+        let (day, year) = (number(day, 2)?, number(year, 4)?);
+        let mut clock = time.split(':').map(|field| number(field, 2));
+        let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+        if clock.next().is_some()
+            || year < 1970
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return None;
+        }
+        let days = days_from_civil(year, month, day);
+        // A day past the end of its month would carry over into the next one.
+        if civil_from_days(days) != (year, month, day) || WEEKDAYS[(days % 7) as usize] != weekday {
+            return None;
+        }
+        let secs = days * SECS_PER_DAY + hour * 3600 + minute * 60 + second;
+        Some(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// The day count since 1970-01-01 of a proleptic Gregorian date from then on, the
+    /// inverse of `civil_from_days`.
+    fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// The proleptic Gregorian (year, month, day) of a day count since 1970-01-01,
+    /// after Howard Hinnant's `civil_from_days`.
+    fn civil_from_days(days: u64) -> (u64, u64, u64) {
+        // Shifted so eras of 400 years start on 0000-03-01, putting leap days last.
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+        (year, month, day)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn at(secs: u64) -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(secs)
+        }
+
+        #[test]
+        fn formats_imf_fixdates() {
+            assert_eq!(format_http_date(at(0)), "Thu, 01 Jan 1970 00:00:00 GMT");
+            assert_eq!(
+                format_http_date(at(784_111_777)),
+                "Sun, 06 Nov 1994 08:49:37 GMT"
+            );
+            assert_eq!(
+                format_http_date(at(951_782_400)),
+                "Tue, 29 Feb 2000 00:00:00 GMT"
+            );
+            assert_eq!(
+                format_http_date(at(1_709_251_199)),
+                "Thu, 29 Feb 2024 23:59:59 GMT"
+            );
+            assert_eq!(
+                format_http_date(at(4_107_542_400)),
+                "Mon, 01 Mar 2100 00:00:00 GMT"
+            );
+        }
+
+        #[test]
+        fn parses_what_it_formats() {
+            for secs in [0, 784_111_777, 951_782_400, 1_709_251_199, 4_107_542_400] {
+                assert_eq!(parse_http_date(&format_http_date(at(secs))), Some(at(secs)));
+            }
+        }
+
+        #[test]
+        fn rejects_days_missing_from_the_calendar() {
+            assert_eq!(parse_http_date("Wed, 29 Feb 2023 00:00:00 GMT"), None);
+            assert_eq!(parse_http_date("Mon, 29 Feb 2100 00:00:00 GMT"), None);
+            assert_eq!(parse_http_date("Fri, 31 Apr 2020 00:00:00 GMT"), None);
+            assert_eq!(parse_http_date("Fri, 32 Jan 2021 00:00:00 GMT"), None);
+        }
+
+        #[test]
+        fn rejects_other_formats() {
+            for value in [
+                "Mon, 06 Nov 1994 08:49:37 GMT",
+                "Sunday, 06-Nov-94 08:49:37 GMT",
+                "Sun Nov  6 08:49:37 1994",
+                "Sun, 6 Nov 1994 08:49:37 GMT",
+                "Sun, 06 nov 1994 08:49:37 GMT",
+                "Sun, 06 Nov 1994 08:49:37 UTC",
+                "Sun, 06 Nov 1994 24:00:00 GMT",
+                "Sun, 06 Nov 1994 08:49 GMT",
+                "Wed, 31 Dec 1969 23:59:59 GMT",
+            ] {
+                assert_eq!(parse_http_date(value), None, "{:?}", value);
+            }
         }
     }
 }
 
 pub mod response {
 
-    use std::collections::HashMap;
+    use std::fmt;
+    use std::fs::File;
+    use std::io::{self, Read, Write};
+    use std::time::SystemTime;
 
     use bytes::BufMut;
 
+    use super::chunked::ChunkedWriter;
+    use super::date;
+    use super::headers::HeaderMap;
     use super::ContentType;
 
+    /// Sent in the `Server` header of responses that don't set their own.
+    pub const SERVER: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+    /// A response payload: held in memory, streamed from an open file, or produced
+    /// by a reader whose length isn't known up front.
+    pub enum Body {
+        Bytes(Vec<u8>),
+        File { file: File, len: u64 },
+        Stream(Box<dyn Read + Send>),
+    }
+
+    impl Body {
+        /// The payload size, or `None` for a stream that has to be sent chunked.
+        pub fn get_content_length(&self) -> Option<u64> {
+            match self {
+                Self::Bytes(bytes) => Some(bytes.len() as u64),
+                Self::File { len, .. } => Some(*len),
+                Self::Stream(_) => None,
+            }
+        }
+
+        /// Writes the payload, copying file contents to `stream` chunk by chunk
+        /// rather than loading them into memory.
+        pub fn write_to<W: Write>(self, stream: &mut W) -> io::Result<()> {
+            match self {
+                Self::Bytes(bytes) => stream.write_all(&bytes),
+                Self::File { file, len } => {
+                    let copied = io::copy(&mut file.take(len), stream)?;
+                    if copied < len {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            format!("file ended after {} of {} bytes", copied, len),
+                        ));
+                    }
+                    Ok(())
+                }
+                Self::Stream(mut reader) => io::copy(&mut reader, stream).map(|_| ()),
+            }
+        }
+    }
+
+    impl fmt::Debug for Body {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+                Self::File { len, .. } => f.debug_struct("File").field("len", len).finish(),
+                Self::Stream(_) => f.write_str("Stream"),
+            }
+        }
+    }
+
     #[derive(Debug)]
     pub struct Content {
         pub content_type: ContentType,
-        pub body: Vec<u8>,
+        pub body: Body,
         pub encoding: Option<String>,
     }
 
@@ -99,144 +1949,620 @@ pub mod response {
     pub struct Response {
         pub http_version: String,
         pub status: super::Status,
-        pub headers: HashMap<String, String>,
+        pub headers: HeaderMap,
         pub content: Option<Content>,
     }
 
-    impl Response {
+    impl fmt::Display for Response {
         //HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\r\nabc
-        pub fn to_string(&self) -> String {
-            let http_version = &self.http_version;
-            let status = &self.status.to_string();
-            let headers = &self
-                .headers
-                .iter()
-                .map(|(key, val)| format!("{}: {}", key, val))
-                .collect::<Vec<String>>()
-                .join("\r\n");
-            let body = if let Some(content) = &self.content {
-                &format!("{}", String::from_utf8_lossy(content.body.as_slice()))
-            } else {
-                ""
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let head = String::from_utf8_lossy(&self.head_as_bytes()).into_owned();
+            let body = match self.content.as_ref().map(|content| &content.body) {
+                Some(Body::Bytes(bytes)) => String::from_utf8_lossy(bytes),
+                Some(Body::File { len, .. }) => {
+                    format!("<{} bytes streamed from file>", len).into()
+                }
+                Some(Body::Stream(_)) => "<streamed body>".into(),
+                None => "".into(),
             };
 
-            format!("{http_version} {status}\r\n{headers}\r\n\r\n{body}")
+            write!(f, "{head}{body}")
         }
+    }
 
-        pub fn as_bytes(&self) -> Vec<u8> {
-            let http_version = &self.http_version;
-            let status = &self.status.to_string();
-            let headers = &self
-                .headers
-                .iter()
-                .map(|(key, val)| format!("{}: {}", key, val))
-                .collect::<Vec<String>>()
-                .join("\r\n");
+    impl Response {
+        pub fn new(status: super::Status) -> Self {
+            Self {
+                http_version: "HTTP/1.1".to_string(),
+                status,
+                headers: HeaderMap::new(),
+                content: None,
+            }
+        }
+
+        pub fn with_content(mut self, content: Content) -> Self {
+            self.content = Some(content);
+            self
+        }
+
+        /// Announces whether the connection stays open after this response. HTTP/1.1
+        /// connections persist by default, so only HTTP/1.0 needs an explicit `keep-alive`.
+        pub fn set_keep_alive(&mut self, keep_alive: bool) {
+            if !keep_alive {
+                self.headers.insert("Connection", "close");
+            } else if self.http_version == "HTTP/1.0" {
+                self.headers.insert("Connection", "keep-alive");
+            }
+        }
 
-            let response_without_body = format!("{http_version} {status}\r\n{headers}\r\n\r\n");
+        /// The status line and headers, up to and including the empty line before the body.
+        /// Headers go out in the order they were added, after `Date` and `Server` unless
+        /// those were set explicitly.
+        pub fn head_as_bytes(&self) -> Vec<u8> {
             let mut result = Vec::<u8>::new();
-            result.put_slice(response_without_body.as_bytes());
-            if let Some(content) = &self.content {
-                result.put_slice(content.body.as_slice());
+            result.put_slice(format!("{} {}\r\n", self.http_version, self.status).as_bytes());
+            if !self.headers.contains("Date") {
+                let now = date::format_http_date(SystemTime::now());
+                result.put_slice(format!("Date: {}\r\n", now).as_bytes());
             }
+            if !self.headers.contains("Server") {
+                result.put_slice(format!("Server: {}\r\n", SERVER).as_bytes());
+            }
+            for (key, val) in &self.headers {
+                result.put_slice(format!("{}: {}\r\n", key, val).as_bytes());
+            }
+            result.put_slice(b"\r\n");
             result
         }
+
+        /// Whether the body is framed with `Transfer-Encoding: chunked`.
+        pub fn is_chunked(&self) -> bool {
+            self.headers
+                .get_transfer_codings()
+                .last()
+                .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+        }
+
+        /// Whether the end of the body can only be signalled by closing the connection,
+        /// as for an HTTP/1.0 stream of unknown length.
+        pub fn is_close_delimited(&self) -> bool {
+            self.content
+                .as_ref()
+                .is_some_and(|content| content.body.get_content_length().is_none())
+                && !self.is_chunked()
+        }
+
+        pub fn write_to<W: Write>(self, stream: &mut W) -> io::Result<()> {
+            stream.write_all(&self.head_as_bytes())?;
+            let chunked = self.is_chunked();
+            if let Some(content) = self.content {
+                if chunked {
+                    let mut writer = ChunkedWriter::new(&mut *stream);
+                    content.body.write_to(&mut writer)?;
+                    writer.finish()?;
+                } else {
+                    content.body.write_to(stream)?;
+                }
+            }
+            stream.flush()
+        }
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub enum HttpMethod {
     #[default]
     Get,
+    Head,
     Post,
-}
-
-#[derive(Debug)]
-pub enum Status {
-    Ok,
-    Created,
-    NotFound,
-    InternalServerError,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Trace,
+    Connect,
+    /// Any other method token; the server doesn't implement these.
+    Extension(String),
 }
 
 impl HttpMethod {
     pub fn to_string(&'_ self) -> &'_ str {
         match self {
             Self::Get => "GET",
+            Self::Head => "HEAD",
             Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Delete => "DELETE",
+            Self::Patch => "PATCH",
+            Self::Options => "OPTIONS",
+            Self::Trace => "TRACE",
+            Self::Connect => "CONNECT",
+            Self::Extension(method) => method,
         }
     }
 
+    /// Method names are case-sensitive, so `get` is an extension method rather than GET.
     pub fn from_string(string: &str) -> HttpMethod {
         match string {
             "GET" => Self::Get,
+            "HEAD" => Self::Head,
             "POST" => Self::Post,
-            _ => panic!("Unable to parse HTTP method from {}", string),
+            "PUT" => Self::Put,
+            "DELETE" => Self::Delete,
+            "PATCH" => Self::Patch,
+            "OPTIONS" => Self::Options,
+            "TRACE" => Self::Trace,
+            "CONNECT" => Self::Connect,
+            _ => Self::Extension(string.to_owned()),
         }
     }
 }
 
-impl Status {
-    pub fn get_status_code(&self) -> u16 {
-        match self {
-            Self::Ok => 200,
-            Self::Created => 201,
-            Self::NotFound => 404,
-            Self::InternalServerError => 500,
+/// Declares `Status` with one variant per registered code, plus the lookups between
+/// variants, codes and reason phrases, from a single table.
+macro_rules! statuses {
+    ($($variant:ident = $code:literal, $reason:literal;)+) => {
+        /// The status codes in the IANA HTTP Status Code Registry. `Custom` carries any
+        /// other code, with its own reason phrase.
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub enum Status {
+            $($variant,)+
+            Custom(u16, String),
+        }
+
+        impl Status {
+            pub fn get_status_code(&self) -> u16 {
+                match self {
+                    $(Self::$variant => $code,)+
+                    Self::Custom(code, _) => *code,
+                }
+            }
+
+            pub fn get_text_code(&'_ self) -> &'_ str {
+                match self {
+                    $(Self::$variant => $reason,)+
+                    Self::Custom(_, reason) => reason,
+                }
+            }
+
+            /// The registered status for `code`, or `Custom` with an empty reason phrase.
+            pub fn from_status_code(code: u16) -> Self {
+                match code {
+                    $($code => Self::$variant,)+
+                    _ => Self::Custom(code, String::new()),
+                }
+            }
         }
+    };
+}
+
+statuses! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Processing = 102, "Processing";
+    EarlyHints = 103, "Early Hints";
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultiStatus = 207, "Multi-Status";
+    AlreadyReported = 208, "Already Reported";
+    ImUsed = 226, "IM Used";
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    UseProxy = 305, "Use Proxy";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableContent = 422, "Unprocessable Content";
+    Locked = 423, "Locked";
+    FailedDependency = 424, "Failed Dependency";
+    TooEarly = 425, "Too Early";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    VariantAlsoNegotiates = 506, "Variant Also Negotiates";
+    InsufficientStorage = 507, "Insufficient Storage";
+    LoopDetected = 508, "Loop Detected";
+    NotExtended = 510, "Not Extended";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
+}
+
+impl Status {
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.get_status_code())
     }
 
-    pub fn get_text_code(&'_ self) -> &'_ str {
-        match self {
-            Self::Ok => "OK",
-            Self::Created => "Created",
-            Self::NotFound => "Not Found",
-            Self::InternalServerError => "Internal Server Error",
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.get_status_code())
+    }
+
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.get_status_code())
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.get_status_code())
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.get_status_code())
+    }
+}
+
+/// Parses the code and optional reason phrase of a status line, e.g. `404 Not Found`.
+/// A registered code gives its variant whatever the phrase; any other code is `Custom`.
+impl FromStr for Status {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (code, reason) = s.split_once(' ').unwrap_or((s, ""));
+        if code.len() != 3 || !code.bytes().all(|b| b.is_ascii_digit()) || code.starts_with('0') {
+            return Err(ParseError::InvalidStatus(s.to_string()));
         }
+        let code: u16 = code
+            .parse()
+            .map_err(|_| ParseError::InvalidStatus(s.to_string()))?;
+        Ok(match Self::from_status_code(code) {
+            Self::Custom(code, _) => Self::Custom(code, reason.to_string()),
+            status => status,
+        })
     }
+}
 
-    pub fn to_string(&self) -> String {
-        format!("{} {}", self.get_status_code(), self.get_text_code())
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.get_status_code(), self.get_text_code())
     }
 }
 
-#[derive(Debug)]
-pub enum TextContentType {
-    Plain,
+/// The characters allowed in tokens: methods, header names, media types and their
+/// parameters.
+pub(crate) fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
 }
 
-#[derive(Debug)]
-pub enum ApplicationContentType {
-    OctetStream,
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(is_tchar)
 }
 
-#[derive(Debug)]
-pub enum ContentType {
-    Text(TextContentType),
-    Application(ApplicationContentType),
+/// A media type such as `text/html; charset=utf-8`: a type, a subtype that may end in
+/// a structured syntax suffix like `+json`, and parameters.
+///
+/// The type, subtype and parameter names are case-insensitive and kept in lowercase;
+/// parameter values are kept as sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    type_: Cow<'static, str>,
+    subtype: Cow<'static, str>,
+    params: Vec<(String, String)>,
 }
 
-impl TextContentType {
-    fn to_string(&self) -> &str {
-        match self {
-            Self::Plain => "plain",
+impl ContentType {
+    pub const TEXT_PLAIN: Self = Self::from_static("text", "plain");
+    pub const TEXT_HTML: Self = Self::from_static("text", "html");
+    pub const TEXT_CSS: Self = Self::from_static("text", "css");
+    pub const TEXT_JAVASCRIPT: Self = Self::from_static("text", "javascript");
+    pub const TEXT_CSV: Self = Self::from_static("text", "csv");
+    pub const TEXT_MARKDOWN: Self = Self::from_static("text", "markdown");
+    pub const TEXT_XML: Self = Self::from_static("text", "xml");
+    pub const APPLICATION_OCTET_STREAM: Self = Self::from_static("application", "octet-stream");
+    pub const APPLICATION_JSON: Self = Self::from_static("application", "json");
+    pub const APPLICATION_XML: Self = Self::from_static("application", "xml");
+    pub const APPLICATION_FORM_URLENCODED: Self =
+        Self::from_static("application", "x-www-form-urlencoded");
+    pub const APPLICATION_WASM: Self = Self::from_static("application", "wasm");
+    pub const APPLICATION_PDF: Self = Self::from_static("application", "pdf");
+    pub const APPLICATION_ZIP: Self = Self::from_static("application", "zip");
+    pub const APPLICATION_GZIP: Self = Self::from_static("application", "gzip");
+    pub const APPLICATION_ZSTD: Self = Self::from_static("application", "zstd");
+    pub const APPLICATION_XZ: Self = Self::from_static("application", "x-xz");
+    pub const APPLICATION_BZIP2: Self = Self::from_static("application", "x-bzip2");
+    pub const APPLICATION_7Z: Self = Self::from_static("application", "x-7z-compressed");
+    pub const MULTIPART_FORM_DATA: Self = Self::from_static("multipart", "form-data");
+    pub const MULTIPART_BYTERANGES: Self = Self::from_static("multipart", "byteranges");
+    pub const IMAGE_PNG: Self = Self::from_static("image", "png");
+    pub const IMAGE_JPEG: Self = Self::from_static("image", "jpeg");
+    pub const IMAGE_GIF: Self = Self::from_static("image", "gif");
+    pub const IMAGE_WEBP: Self = Self::from_static("image", "webp");
+    pub const IMAGE_AVIF: Self = Self::from_static("image", "avif");
+    pub const IMAGE_SVG: Self = Self::from_static("image", "svg+xml");
+    pub const IMAGE_ICON: Self = Self::from_static("image", "x-icon");
+    pub const FONT_WOFF: Self = Self::from_static("font", "woff");
+    pub const FONT_WOFF2: Self = Self::from_static("font", "woff2");
+    pub const AUDIO_MPEG: Self = Self::from_static("audio", "mpeg");
+    pub const AUDIO_OGG: Self = Self::from_static("audio", "ogg");
+    pub const VIDEO_MP4: Self = Self::from_static("video", "mp4");
+    pub const VIDEO_WEBM: Self = Self::from_static("video", "webm");
+
+    const fn from_static(type_: &'static str, subtype: &'static str) -> Self {
+        Self {
+            type_: Cow::Borrowed(type_),
+            subtype: Cow::Borrowed(subtype),
+            params: Vec::new(),
         }
     }
+
+    /// `type/subtype` without parameters. Both have to be tokens.
+    pub fn new(type_: &str, subtype: &str) -> Result<Self, ParseError> {
+        if !is_token(type_) || !is_token(subtype) {
+            return Err(ParseError::InvalidMediaType(format!(
+                "{}/{}",
+                type_, subtype
+            )));
+        }
+        Ok(Self {
+            type_: Cow::Owned(type_.to_ascii_lowercase()),
+            subtype: Cow::Owned(subtype.to_ascii_lowercase()),
+            params: Vec::new(),
+        })
+    }
+
+    pub fn get_type(&'_ self) -> &'_ str {
+        &self.type_
+    }
+
+    pub fn get_subtype(&'_ self) -> &'_ str {
+        &self.subtype
+    }
+
+    /// The structured syntax suffix, e.g. `json` for `application/ld+json`.
+    pub fn get_suffix(&'_ self) -> Option<&'_ str> {
+        self.subtype.rsplit_once('+').map(|(_, suffix)| suffix)
+    }
+
+    /// `type/subtype` without the parameters.
+    pub fn get_essence(&self) -> String {
+        format!("{}/{}", self.type_, self.subtype)
+    }
+
+    /// The parameters in the order they were given, names in lowercase.
+    pub fn get_params(&'_ self) -> &'_ [(String, String)] {
+        &self.params
+    }
+
+    pub fn get_param(&'_ self, name: &str) -> Option<&'_ str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_charset(&'_ self) -> Option<&'_ str> {
+        self.get_param("charset")
+    }
+
+    /// The delimiter between the parts of a `multipart/*` body.
+    pub fn get_boundary(&'_ self) -> Option<&'_ str> {
+        self.get_param("boundary")
+    }
+
+    /// Sets the parameter `name`, replacing any value it had.
+    ///
+    /// Panics if `name` isn't a token, as that can only be a programming error.
+    pub fn with_param(mut self, name: &str, value: impl Into<String>) -> Self {
+        assert!(
+            is_token(name),
+            "Invalid media type parameter name: {}",
+            name
+        );
+        let name = name.to_ascii_lowercase();
+        self.params.retain(|(param, _)| *param != name);
+        self.params.push((name, value.into()));
+        self
+    }
+
+    pub fn with_charset(self, charset: impl Into<String>) -> Self {
+        self.with_param("charset", charset)
+    }
+
+    /// Whether both have the same type and subtype, whatever their parameters.
+    pub fn has_essence_of(&self, other: &Self) -> bool {
+        self.type_ == other.type_ && self.subtype == other.subtype
+    }
+
+    /// Whether the type is textual, so a `charset` parameter applies to it.
+    pub fn is_text(&self) -> bool {
+        self.type_ == "text"
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.type_ == "multipart"
+    }
 }
 
-impl ApplicationContentType {
-    fn to_string(&self) -> &str {
-        match self {
-            Self::OctetStream => "octet-stream",
+/// Writes parameter values as tokens where possible and as quoted strings otherwise.
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.type_, self.subtype)?;
+        for (name, value) in &self.params {
+            if is_token(value) {
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                write!(f, "; {}=\"", name)?;
+                for c in value.chars() {
+                    if c == '"' || c == '\\' {
+                        f.write_str("\\")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+                f.write_str("\"")?;
+            }
         }
+        Ok(())
     }
 }
 
-impl ContentType {
-    pub fn to_string(&self) -> String {
-        match self {
-            Self::Text(sub_type) => format!("text/{}", sub_type.to_string()),
-            Self::Application(sub_type) => format!("application/{}", sub_type.to_string()),
+/// Parses `type/subtype` followed by `;`-separated `name=value` parameters, values
+/// being tokens or quoted strings, as in a `Content-Type` header.
+impl FromStr for ContentType {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::InvalidMediaType(s.to_string());
+        let (essence, mut rest) = s.split_once(';').unwrap_or((s, ""));
+        let (type_, subtype) = essence.trim().split_once('/').ok_or_else(invalid)?;
+        let mut content_type = Self::new(type_, subtype).map_err(|_| invalid())?;
+
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ';']);
+            if rest.is_empty() {
+                return Ok(content_type);
+            }
+            let (name, value) = rest.split_once('=').ok_or_else(invalid)?;
+            if !is_token(name) {
+                return Err(invalid());
+            }
+            let value = if let Some(quoted) = value.strip_prefix('"') {
+                let mut unquoted = String::new();
+                let mut chars = quoted.char_indices();
+                loop {
+                    match chars.next().ok_or_else(invalid)? {
+                        (_, '\\') => unquoted.push(chars.next().ok_or_else(invalid)?.1),
+                        (end, '"') => {
+                            rest = &quoted[end + 1..];
+                            break;
+                        }
+                        (_, c) => unquoted.push(c),
+                    }
+                }
+                let after = rest.trim_start_matches([' ', '\t']);
+                if !after.is_empty() && !after.starts_with(';') {
+                    return Err(invalid());
+                }
+                unquoted
+            } else {
+                let (token, remainder) = value.split_once(';').unwrap_or((value, ""));
+                let token = token.trim_end_matches([' ', '\t']);
+                if !is_token(token) {
+                    return Err(invalid());
+                }
+                rest = remainder;
+                token.to_string()
+            };
+            // The first occurrence of a parameter wins.
+            let name = name.to_ascii_lowercase();
+            if content_type.get_param(&name).is_none() {
+                content_type.params.push((name, value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<ContentType, ParseError> {
+        s.parse()
+    }
+
+    fn params(content_type: &ContentType) -> Vec<(&str, &str)> {
+        content_type
+            .get_params()
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn folds_the_case_of_names_but_not_values() {
+        let content_type = parse(" Text/HTML ; Charset=UTF-8 ").unwrap();
+        assert_eq!(content_type.get_essence(), "text/html");
+        assert_eq!(params(&content_type), [("charset", "UTF-8")]);
+        assert_eq!(content_type.get_charset(), Some("UTF-8"));
+        assert_eq!(content_type.get_param("CHARSET"), Some("UTF-8"));
+        assert_eq!(parse("text/plain;").unwrap(), ContentType::TEXT_PLAIN);
+    }
+
+    #[test]
+    fn unquotes_quoted_values() {
+        let content_type = parse(r#"multipart/form-data; boundary="a\"b\\c;d=e"; x=1"#).unwrap();
+        assert_eq!(
+            params(&content_type),
+            [("boundary", r#"a"b\c;d=e"#), ("x", "1")]
+        );
+        let content_type = parse(r#"text/plain; a="" ;b="x y"	;c=d"#).unwrap();
+        assert_eq!(params(&content_type), [("a", ""), ("b", "x y"), ("c", "d")]);
+    }
+
+    #[test]
+    fn keeps_the_first_of_a_repeated_parameter() {
+        let content_type = parse("text/plain; charset=utf-8; CHARSET=latin1").unwrap();
+        assert_eq!(params(&content_type), [("charset", "utf-8")]);
+    }
+
+    #[test]
+    fn rejects_malformed_media_types() {
+        for s in [
+            "",
+            "text",
+            "text/",
+            "/plain",
+            "te xt/plain",
+            "text/plain; charset=",
+            "text/plain; charset",
+            "text/plain; =utf-8",
+            "text/plain; char set=utf-8",
+            "text/plain; charset=utf 8",
+            r#"text/plain; a="b"c"#,
+            r#"text/plain; a="b" c=d"#,
+            r#"text/plain; a="b"#,
+            r#"text/plain; a="b\"#,
+        ] {
+            assert_eq!(
+                parse(s).unwrap_err(),
+                ParseError::InvalidMediaType(s.to_string()),
+                "{:?}",
+                s
+            );
         }
     }
+
+    #[test]
+    fn quotes_values_that_are_not_tokens() {
+        let content_type = ContentType::TEXT_PLAIN
+            .with_charset("utf-8")
+            .with_param("name", "a b")
+            .with_param("path", r#"C:\"x""#)
+            .with_param("empty", "");
+        let displayed = content_type.to_string();
+        assert_eq!(
+            displayed,
+            r#"text/plain; charset=utf-8; name="a b"; path="C:\\\"x\""; empty="""#
+        );
+        assert_eq!(parse(&displayed).unwrap(), content_type);
+    }
 }
@@ -0,0 +1,11 @@
+pub mod compression;
+pub mod config;
+pub mod files;
+pub mod handler;
+pub mod http;
+pub mod log;
+pub mod middleware;
+pub mod mime;
+pub mod pool;
+pub mod range;
+pub mod router;
@@ -1,212 +1,453 @@
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::Error;
-use std::net::TcpListener;
-use std::{fs, thread};
-use std::{
-    io::{Read, Write},
-    net::TcpStream,
-};
+use std::fs::{File, Metadata};
+use std::io::{Error, ErrorKind, Seek};
+use std::net::{Ipv4Addr, Ipv6Addr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{io::Read, net::TcpStream};
 
-mod http;
-use http::HttpMethod;
+use codecrafters_http_server::config::{Config, ConfigError, USAGE};
+use codecrafters_http_server::files::{self, FileStore, WriteOutcome};
+use codecrafters_http_server::handler::{Chain, Handler, HttpError};
+use codecrafters_http_server::http::date::format_http_date;
+use codecrafters_http_server::http::HttpMethod;
+use codecrafters_http_server::middleware::{
+    BearerAuth, Compression, ContentHeaders, Cors, Logging, RangeRequests,
+};
+use codecrafters_http_server::mime::{MimeTypes, SNIFF_LEN};
+use codecrafters_http_server::pool::{Backlog, ThreadPool};
+use codecrafters_http_server::router::Router;
+use codecrafters_http_server::{log, log_debug, log_error, log_info, log_warn};
 
-use crate::http::request::Request;
-use crate::http::response::Content;
-use crate::http::response::Response;
-use crate::http::ApplicationContentType;
-use crate::http::ContentType;
-use crate::http::Status;
-use crate::http::TextContentType;
+use codecrafters_http_server::http::reader::{Limits, ReadError, RequestReader};
+use codecrafters_http_server::http::request::Request;
+use codecrafters_http_server::http::response::Response;
+use codecrafters_http_server::http::response::{Body, Content};
+use codecrafters_http_server::http::ContentType;
+use codecrafters_http_server::http::Status;
 
 const BUF_SIZE: usize = 1024;
-const GZIP_ENCODING: &str = "gzip";
+const RETRY_AFTER: Duration = Duration::from_secs(1);
+const REJECT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+/// How often idle connections check whether to shut down.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Files up to this size are read into memory (and may be compressed); larger ones are streamed.
+const FILE_STREAMING_THRESHOLD: u64 = 64 * 1024;
 
 fn main() {
-    // You can use print statements as follows for debugging, they'll be visible when running tests.
-    println!("Logs from your program will appear here!");
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(ConfigError::HelpRequested) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+    log::set_level(config.log_level);
+    let config = Arc::new(config);
+
+    let listener = match TcpListener::bind((config.bind, config.port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            log_error!("Failed to bind {}:{}: {}", config.bind, config.port, err);
+            std::process::exit(1);
+        }
+    };
+    log_info!("Listening on {}:{}", config.bind, config.port);
 
-    let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
+    // The first SIGINT/SIGTERM starts a graceful shutdown, a second one exits at once.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        let registered =
+            signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&shutdown))
+                .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&shutdown)));
+        if let Err(err) = registered {
+            log_error!("Failed to register a signal handler: {}", err);
+            std::process::exit(1);
+        }
+    }
+    if let Err(err) = spawn_shutdown_waker(&listener) {
+        log_error!("Failed to watch for shutdown signals: {}", err);
+        std::process::exit(1);
+    }
+
+    let pool = {
+        let config = Arc::clone(&config);
+        let shutdown = Arc::clone(&shutdown);
+        let app = build_app(&config);
+        ThreadPool::new(
+            config.threads,
+            config.queue_size,
+            config.overload_policy,
+            move |stream: TcpStream, backlog: &Backlog| {
+                handle_connection(stream, &config, &app, &shutdown, backlog)
+            },
+        )
+    };
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(_stream) => {
-                thread::spawn(|| {
-                    handle_connection(_stream);
-                });
+    loop {
+        let accepted = listener.accept();
+        // The connection that woke the loop up for a shutdown isn't served.
+        if shutdown.load(Ordering::Relaxed) {
+            break;
+        }
+        match accepted {
+            Ok((stream, _)) => {
+                if let Err(rejected) = pool.submit(stream) {
+                    reject_connection(rejected);
+                }
             }
             Err(e) => {
-                println!("error: {}", e);
+                log_warn!("Failed to accept a connection: {}", e);
             }
         }
     }
+
+    drop(listener);
+    log_info!(
+        "Shutting down, waiting up to {:?} for requests in flight",
+        config.shutdown_timeout
+    );
+    if pool.shutdown(config.shutdown_timeout) {
+        log_info!("Shut down cleanly");
+    } else {
+        log_warn!("Shut down with requests still in flight");
+    }
 }
 
-fn read_data<const N: usize>(stream: &mut TcpStream) -> Result<(usize, [u8; N]), Error> {
-    let mut buf: [u8; N] = [0; N];
-    let result = stream.read(&mut buf[..]);
-    match result {
-        Ok(bytes_read) => Ok((bytes_read, buf)),
-        Err(err) => Err(err),
+/// Connects to `listener` once SIGINT or SIGTERM arrives, so the accept loop wakes up
+/// from its blocking `accept` and sees the shutdown flag.
+fn spawn_shutdown_waker(listener: &TcpListener) -> std::io::Result<()> {
+    let mut addr = listener.local_addr()?;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(if addr.is_ipv4() {
+            Ipv4Addr::LOCALHOST.into()
+        } else {
+            Ipv6Addr::LOCALHOST.into()
+        });
     }
+    let mut signals = signal_hook::iterator::Signals::new([
+        signal_hook::consts::SIGINT,
+        signal_hook::consts::SIGTERM,
+    ])?;
+    thread::Builder::new()
+        .name("shutdown-waker".to_string())
+        .spawn(move || {
+            if signals.forever().next().is_some() {
+                if let Err(err) = TcpStream::connect(addr) {
+                    log_warn!("Failed to wake the accept loop up: {}", err);
+                }
+            }
+        })?;
+    Ok(())
 }
 
-fn handle_request(req: &Request) -> Response {
-    let mut status: Status;
-    let mut content: Option<Content>;
-    let request_path = req.get_path();
-    if request_path.eq("/") {
-        status = Status::Ok;
-        content = None;
-    } else if request_path.eq("/user-agent") {
-        status = Status::Ok;
-        content = Some(Content {
-            content_type: ContentType::Text(TextContentType::Plain),
-            body: req
-                .get_headers()
-                .get("User-Agent")
-                .unwrap()
+/// Turns a connection away while every worker is busy and the queue is full.
+fn reject_connection(stream: TcpStream) {
+    log_warn!("Server overloaded, rejecting a connection");
+    // This runs on the accept loop, so don't let a slow client hold it up.
+    if let Err(err) = stream.set_write_timeout(Some(REJECT_WRITE_TIMEOUT)) {
+        log_debug!("Failed to set the write timeout: {}", err);
+        return;
+    }
+    let mut res = Response::new(Status::ServiceUnavailable);
+    res.headers.insert("Content-Length", "0");
+    res.headers
+        .insert("Retry-After", RETRY_AFTER.as_secs().to_string());
+    res.set_keep_alive(false);
+    if let Err(err) = res.write_to(&mut &stream) {
+        log_debug!("Failed to write the 503 response: {}", err);
+    }
+}
+
+fn build_router(config: &Config) -> Router {
+    let mut router = Router::new();
+    router
+        .get("/", |_| Response::new(Status::Ok))
+        .get("/user-agent", handle_user_agent)
+        .get("/echo/*text", handle_echo);
+    if let Some(file_store) = config.get_file_store() {
+        let file_store = Arc::new(file_store);
+        let (get_store, post_store, put_store, delete_store) = (
+            Arc::clone(&file_store),
+            Arc::clone(&file_store),
+            Arc::clone(&file_store),
+            file_store,
+        );
+        let mime_types = config.mime_types.clone();
+        router
+            .get("/files/*name", move |req| {
+                handle_get_file(req, &get_store, &mime_types)
+            })
+            .post("/files/*name", move |req| {
+                handle_post_file(req, &post_store)
+            })
+            .put("/files/*name", move |req| handle_put_file(req, &put_store))
+            .delete("/files/*name", move |req| {
+                handle_delete_file(req, &delete_store)
+            });
+    }
+    router
+}
+
+fn handle_user_agent(req: &Request) -> Result<Response, HttpError> {
+    let user_agent = req
+        .get_header("User-Agent")
+        .ok_or_else(|| HttpError::BadRequest("Missing User-Agent header".to_string()))?;
+    Ok(Response::new(Status::Ok).with_content(Content {
+        content_type: ContentType::TEXT_PLAIN,
+        body: Body::Bytes(user_agent.as_bytes().to_vec()),
+        encoding: None,
+    }))
+}
+
+fn handle_echo(req: &Request) -> Response {
+    Response::new(Status::Ok).with_content(Content {
+        content_type: ContentType::TEXT_PLAIN,
+        body: Body::Bytes(
+            req.get_param("text")
+                .unwrap_or_default()
                 .as_bytes()
                 .to_vec(),
-            encoding: None,
+        ),
+        encoding: None,
+    })
+}
+
+fn handle_get_file(
+    req: &Request,
+    file_store: &FileStore,
+    mime_types: &MimeTypes,
+) -> Result<Response, HttpError> {
+    let name = req.get_param("name").unwrap_or_default();
+    let (content, metadata) = file_store
+        .open(name)
+        .and_then(|file| {
+            let metadata = file.metadata()?;
+            Ok((
+                read_file_content(file, &metadata, name, mime_types)?,
+                metadata,
+            ))
+        })
+        .map_err(|err| file_error(name, err))?;
+    // Validators for `If-Range`, and for caches.
+    let mut res = Response::new(Status::Ok).with_content(content);
+    res.headers.insert("ETag", files::get_etag(&metadata));
+    if let Ok(modified) = metadata.modified() {
+        res.headers
+            .insert("Last-Modified", format_http_date(modified));
+    }
+    Ok(res)
+}
+
+fn handle_post_file(req: &Request, file_store: &FileStore) -> Result<Response, HttpError> {
+    let name = req.get_param("name").unwrap_or_default();
+    file_store
+        .post(name, req.get_body())
+        .map_err(|err| file_error(name, err))?;
+    Ok(Response::new(Status::Created))
+}
+
+fn handle_put_file(req: &Request, file_store: &FileStore) -> Result<Response, HttpError> {
+    let name = req.get_param("name").unwrap_or_default();
+    match file_store
+        .put(name, req.get_body())
+        .map_err(|err| file_error(name, err))?
+    {
+        WriteOutcome::Created => Ok(Response::new(Status::Created)),
+        WriteOutcome::Replaced => Ok(Response::new(Status::NoContent)),
+    }
+}
+
+fn handle_delete_file(req: &Request, file_store: &FileStore) -> Result<Response, HttpError> {
+    let name = req.get_param("name").unwrap_or_default();
+    file_store
+        .delete(name)
+        .map_err(|err| file_error(name, err))?;
+    Ok(Response::new(Status::NoContent))
+}
+
+fn file_error(name: &str, err: Error) -> HttpError {
+    log_warn!("Error when accessing file {}: {}", name, err);
+    err.into()
+}
+
+/// The routes wrapped in the middleware enabled by `config`.
+fn build_app(config: &Config) -> Chain {
+    let mut app = Chain::new(build_router(config))
+        .with(Logging)
+        .with(ContentHeaders);
+    if let Some(origin) = config.cors_origin.as_ref() {
+        app = app.with(Cors {
+            allowed_origin: origin.clone(),
+            allowed_methods: config.cors_methods.clone(),
+            allowed_headers: config.cors_headers.clone(),
         });
-    } else if request_path.starts_with("/echo/") {
-        status = Status::Ok;
-        content = Some(Content {
-            content_type: ContentType::Text(TextContentType::Plain),
-            body: request_path
-                .trim_start_matches("/echo/")
-                .as_bytes()
-                .to_vec(),
-            encoding: None,
+    }
+    if let Some(token) = config.auth_token.as_ref() {
+        app = app.with(BearerAuth {
+            token: token.clone(),
         });
-    } else if request_path.starts_with("/files/") {
-        let filename = request_path.trim_start_matches("/files/");
-        let file_path: String = get_file_root_dir()
-            .map(|file_root_dir| file_root_dir + filename)
-            .expect("Could not read the `--directory` flag value.");
-        match req.get_method() {
-            HttpMethod::Get => match read_file_content(&file_path) {
-                Ok(_content) => {
-                    status = Status::Ok;
-                    content = Some(_content);
-                }
+    }
+    app.with(Compression {
+        policy: config.compression_policy.clone(),
+    })
+    .with(RangeRequests)
+}
+
+fn handle_request(req: &mut Request, app: &dyn Handler) -> Response {
+    let mut res = app.handle(req);
+    res.http_version = req.get_http_version().to_owned();
+
+    // HEAD gets exactly the headers of the matching GET, including the length
+    // and type of the body it would have had, but no body.
+    if *req.get_method() == HttpMethod::Head {
+        res.content = None;
+    }
+    res
+}
+
+/// Serves requests from the stream until the client asks to close the connection,
+/// sends something that can't be answered, or stays silent for the idle timeout.
+/// Between requests, the connection gives its worker up to anyone in `backlog`.
+fn handle_connection(
+    stream: TcpStream,
+    config: &Config,
+    app: &dyn Handler,
+    shutdown: &AtomicBool,
+    backlog: &Backlog,
+) {
+    let limits = Limits {
+        max_header_size: config.max_header_size,
+        max_body_size: config.max_body_size,
+        header_timeout: config.header_timeout,
+        body_timeout: config.body_timeout,
+    };
+    let mut reader = RequestReader::<_, BUF_SIZE>::new(&stream, limits);
+    let mut served = false;
+    loop {
+        if !reader.has_buffered_data() {
+            let backlog = served.then_some(backlog);
+            match wait_for_request(&stream, config.idle_timeout, shutdown, backlog) {
+                Ok(true) => {}
+                Ok(false) => return,
                 Err(err) => {
-                    dbg!("Error when reading file at {}: {:?}", &file_path, &err);
-                    status = Status::NotFound;
-                    content = None;
+                    log_debug!("Failed to wait for a request: {}", err);
+                    return;
                 }
-            },
-            HttpMethod::Post => match File::create(&file_path) {
-                Ok(mut file) => {
-                    match req
-                        .get_body()
-                        .as_ref()
-                        .map(|body| file.write(body.as_bytes()))
-                    {
-                        Some(Err(err)) => {
-                            dbg!("Error when writing to file at {}: {:?}", &file_path, &err);
-                            status = Status::InternalServerError;
-                            content = None;
-                        }
-                        _ => {
-                            status = Status::Created;
-                            content = None;
-                        }
-                    }
-                }
-                Err(err) => {
-                    dbg!("Error when creating file at {}: {:?}", &file_path, &err);
-                    status = Status::InternalServerError;
-                    content = None;
+            }
+        }
+        if let Err(err) = stream.set_read_timeout(Some(config.idle_timeout)) {
+            log_error!("Failed to set the read timeout: {}", err);
+            return;
+        }
+        let (res, keep_alive) = match reader.read_request() {
+            Ok(Some(mut req)) => {
+                let mut res = handle_request(&mut req, app);
+                // Once shutting down, the response in flight is the connection's last.
+                let keep_alive = req.wants_keep_alive()
+                    && !res.is_close_delimited()
+                    && !shutdown.load(Ordering::Relaxed);
+                res.set_keep_alive(keep_alive);
+                (res, keep_alive)
+            }
+            Ok(None) => return,
+            Err(err) => match error_response(&err) {
+                Some(res) => (res, false),
+                None => {
+                    log_debug!("Failed to read request: {}", err);
+                    return;
                 }
             },
+        };
+        if let Err(err) = res.write_to(&mut &stream) {
+            log_debug!("Failed to write the response: {}", err);
+            return;
         }
-    } else {
-        status = Status::NotFound;
-        content = None;
+        if !keep_alive {
+            return;
+        }
+        served = true;
     }
+}
 
-    let accepted_encodings: HashSet<&str> = req
-        .get_headers()
-        .get("Accept-Encoding")
-        .iter()
-        .flat_map(|list| list.split(','))
-        .map(str::trim)
-        .collect::<HashSet<&str>>();
-
-    if accepted_encodings.contains(GZIP_ENCODING) {
-        if let Some(_content) = content.as_ref() {
-            match gzip(_content.body.as_slice()) {
-                Ok(payload) => {
-                    content = content.map(|c| Content {
-                        content_type: c.content_type,
-                        body: payload,
-                        encoding: Some(GZIP_ENCODING.to_owned()),
-                    });
-                }
-                Err(err) => {
-                    dbg!("Failed to Gzip the content: {}", err);
-                    status = Status::InternalServerError;
-                    content = None;
+/// Waits for the next request on an idle connection. Returns `false` when the peer
+/// closed it, it stayed idle for `idle_timeout`, the server is shutting down, or,
+/// given a `backlog`, other connections are waiting for a worker or were turned
+/// away while this one sat idle.
+fn wait_for_request(
+    stream: &TcpStream,
+    idle_timeout: Duration,
+    shutdown: &AtomicBool,
+    backlog: Option<&Backlog>,
+) -> std::io::Result<bool> {
+    stream.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+    let idle_since = Instant::now();
+    let rejected = backlog.map(Backlog::get_rejected);
+    let mut byte = [0u8; 1];
+    loop {
+        match stream.peek(&mut byte) {
+            Ok(0) => return Ok(false),
+            Ok(_) => return Ok(true),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if shutdown.load(Ordering::Relaxed)
+                    || idle_since.elapsed() >= idle_timeout
+                    || backlog.is_some_and(|backlog| {
+                        !backlog.is_empty() || Some(backlog.get_rejected()) != rejected
+                    })
+                {
+                    return Ok(false);
                 }
             }
+            Err(err) => return Err(err),
         }
     }
-
-    let mut headers: HashMap<String, String> = HashMap::new();
-    if let Some(_content) = content.as_ref() {
-        headers.insert(
-            "Content-Type".to_string(),
-            _content.content_type.to_string(),
-        );
-        headers.insert(
-            "Content-Length".to_string(),
-            _content.body.len().to_string(),
-        );
-        if let Some(encoding) = _content.encoding.as_ref() {
-            headers.insert("Content-Encoding".to_string(), encoding.clone());
-        }
-    }
-
-    Response {
-        http_version: req.get_http_version().to_owned(),
-        status: status,
-        headers: headers,
-        content: content,
-    }
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let (bytes_read, buf) =
-        read_data::<BUF_SIZE>(&mut stream).expect("Failed to read data from stream.");
-    if bytes_read > 0 {
-        let req =
-            Request::from_raw(&buf[..bytes_read]).expect("Failed to read request from raw input.");
-        let res = handle_request(&req);
-        dbg!("Response: {}", res.to_string());
-        stream
-            .write(res.as_bytes().as_slice())
-            .expect("Failed to write to the incoming connection's stream.");
-    }
+/// Answers a request that couldn't be read. It never reaches the middleware, so the
+/// content headers are set here.
+fn error_response(err: &ReadError) -> Option<Response> {
+    let status = err.get_status()?;
+    log_debug!("Rejecting request: {}", err);
+    let message = err.to_string();
+    let mut res = Response::new(status);
+    res.headers
+        .insert("Content-Type", ContentType::TEXT_PLAIN.to_string());
+    res.headers
+        .insert("Content-Length", message.len().to_string());
+    res.set_keep_alive(false);
+    Some(res.with_content(Content {
+        content_type: ContentType::TEXT_PLAIN,
+        body: Body::Bytes(message.into_bytes()),
+        encoding: None,
+    }))
 }
 
-fn read_file_content(path: &str) -> Result<Content, Error> {
-    fs::read_to_string(&path).map(|content| Content {
-        content_type: ContentType::Application(ApplicationContentType::OctetStream),
-        body: content.as_bytes().to_vec(),
-        encoding: None, // TODO: set encoding according to the file's extension
+fn read_file_content(
+    mut file: File,
+    metadata: &Metadata,
+    name: &str,
+    mime_types: &MimeTypes,
+) -> Result<Content, Error> {
+    let len = metadata.len();
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    let body = if len > FILE_STREAMING_THRESHOLD {
+        (&file).take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+        file.rewind()?;
+        Body::File { file, len }
+    } else {
+        let mut bytes = Vec::with_capacity(len as usize);
+        (&file).read_to_end(&mut bytes)?;
+        head.extend_from_slice(&bytes[..bytes.len().min(SNIFF_LEN)]);
+        Body::Bytes(bytes)
+    };
+    Ok(Content {
+        content_type: mime_types.detect(name, &head),
+        body,
+        encoding: None,
     })
 }
-
-fn get_file_root_dir() -> Option<String> {
-    std::env::args().nth(2)
-}
-
-fn gzip(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
-    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(bytes)?;
-    encoder.finish()
-}
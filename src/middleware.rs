@@ -0,0 +1,284 @@
+use std::time::Instant;
+
+use crate::compression::{self, AcceptEncoding, CompressionPolicy, ContentCoding, Negotiation};
+use crate::handler::{Handler, HttpError, Middleware};
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::http::{HttpMethod, Status};
+use crate::log_info;
+use crate::range::{self, RangeSelection};
+
+/// Logs every request with the status it got and how long it took.
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn handle(&self, req: &mut Request, next: &dyn Handler) -> Response {
+        let started = Instant::now();
+        let res = next.handle(req);
+        log_info!(
+            "{} {} -> {} ({:.1?})",
+            req.get_method().to_string(),
+            req.get_path(),
+            res.status,
+            started.elapsed()
+        );
+        res
+    }
+}
+
+/// Describes the body in the headers: `Content-Type`, `Content-Encoding` and either
+/// `Content-Length` or, for streams of unknown length, `Transfer-Encoding: chunked`.
+pub struct ContentHeaders;
+
+impl Middleware for ContentHeaders {
+    fn handle(&self, req: &mut Request, next: &dyn Handler) -> Response {
+        let mut res = next.handle(req);
+        let headers = &mut res.headers;
+        match res.content.as_ref() {
+            None if !matches!(res.status, Status::NoContent) => {
+                headers.insert("Content-Length", "0");
+            }
+            None => {}
+            Some(content) => {
+                headers.insert("Content-Type", content.content_type.to_string());
+                match content.body.get_content_length() {
+                    Some(len) => {
+                        headers.insert("Content-Length", len.to_string());
+                    }
+                    // HTTP/1.0 clients don't understand chunked, so the body ends with the connection.
+                    None if req.get_http_version() == "HTTP/1.0" => {}
+                    None => {
+                        headers.insert("Transfer-Encoding", "chunked");
+                    }
+                }
+                if let Some(encoding) = content.encoding.as_ref() {
+                    headers.insert("Content-Encoding", encoding.clone());
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Compresses bodies the policy deems worth it with the content coding the client
+/// weighs highest among those the server supports. A successful response the client
+/// refuses every coding for, `identity` included, becomes 406 instead, whether or not
+/// the policy would have compressed it.
+///
+/// As the outcome depends on `Accept-Encoding`, every response with a body says so in
+/// `Vary`.
+pub struct Compression {
+    pub policy: CompressionPolicy,
+}
+
+impl Middleware for Compression {
+    fn handle(&self, req: &mut Request, next: &dyn Handler) -> Response {
+        let mut res = next.handle(req);
+        let is_partial = res.status == Status::PartialContent;
+        let (content_type, len) = match res.content.as_ref() {
+            Some(content) if content.encoding.is_none() => (
+                content.content_type.to_string(),
+                // A range says nothing about the size of the whole body.
+                content.body.get_content_length().filter(|_| !is_partial),
+            ),
+            _ => return res,
+        };
+        res.headers.add_to_list("Vary", "Accept-Encoding");
+        let accept_encoding = match AcceptEncoding::from_headers(req.get_headers()) {
+            Some(accept_encoding) => accept_encoding,
+            None => return res,
+        };
+        let negotiation = accept_encoding.negotiate(&ContentCoding::ALL);
+        if negotiation == Negotiation::NotAcceptable && res.status.is_success() {
+            let mut res = Response::new(Status::NotAcceptable);
+            res.headers.add_to_list("Vary", "Accept-Encoding");
+            return res;
+        }
+        // A range of the unencoded body can't be compressed on its own. Otherwise a
+        // client refusing the unencoded body gets it compressed whatever the policy.
+        if is_partial
+            || (accept_encoding.accepts_identity()
+                && !self.policy.should_compress(&content_type, len))
+        {
+            return res;
+        }
+
+        let coding = match negotiation {
+            Negotiation::Encode(coding) => coding,
+            Negotiation::NotAcceptable | Negotiation::Identity => return res,
+        };
+        let level = self.policy.get_level(&content_type, coding);
+        let encoded = res
+            .content
+            .map(|content| compression::encode_content(content, coding, level))
+            .transpose();
+        match encoded {
+            Ok(encoded) => res.content = encoded,
+            Err(err) => return HttpError::from(err).into(),
+        }
+        // Ranges are served from the unencoded body, not this one.
+        res.headers.remove("Accept-Ranges");
+        // The encoded body differs byte for byte from the one the tag was made for.
+        if let Some(etag) = res
+            .headers
+            .get("ETag")
+            .filter(|etag| !etag.starts_with("W/"))
+        {
+            let weak = format!("W/{}", etag);
+            res.headers.insert("ETag", weak);
+        }
+        res
+    }
+}
+
+/// Answers GET requests with `Range: bytes=...` with only the parts asked for: 206
+/// with `Content-Range` for a single range, a `multipart/byteranges` body for several,
+/// or 416 if none of them overlaps the body. With `If-Range`, the whole body is sent
+/// instead once it has changed.
+///
+/// Successful responses of known length advertise `Accept-Ranges: bytes`.
+pub struct RangeRequests;
+
+impl Middleware for RangeRequests {
+    fn handle(&self, req: &mut Request, next: &dyn Handler) -> Response {
+        let mut res = next.handle(req);
+        let len = match res.content.as_ref() {
+            Some(content) if res.status == Status::Ok && content.encoding.is_none() => {
+                match content.body.get_content_length() {
+                    Some(len) => len,
+                    None => return res,
+                }
+            }
+            _ => return res,
+        };
+        res.headers.insert("Accept-Ranges", "bytes");
+        // GET is the only method ranges are defined for.
+        let range = match req.get_header("Range") {
+            Some(range) if *req.get_method() == HttpMethod::Get => range,
+            _ => return res,
+        };
+        if let Some(if_range) = req.get_header("If-Range") {
+            if !range::if_range_matches(if_range, &res.headers) {
+                return res;
+            }
+        }
+
+        let ranges = match range::select_ranges(range, len) {
+            RangeSelection::Full => return res,
+            RangeSelection::Partial(ranges) => ranges,
+            RangeSelection::Unsatisfiable => {
+                let mut res = Response::new(Status::RangeNotSatisfiable);
+                res.headers
+                    .insert("Content-Range", format!("bytes */{}", len));
+                return res;
+            }
+        };
+        let content = res.content.take().expect("Checked above.");
+        let partial = match ranges[..] {
+            [range] => {
+                res.headers
+                    .insert("Content-Range", range.to_content_range(len));
+                range::slice_content(content, range)
+            }
+            _ => range::multipart_content(content, &ranges, len),
+        };
+        match partial {
+            Ok(partial) => {
+                res.status = Status::PartialContent;
+                res.content = Some(partial);
+                res
+            }
+            Err(err) => HttpError::from(err).into(),
+        }
+    }
+}
+
+/// Methods a preflight allows unless configured otherwise: those the routes answer.
+pub const DEFAULT_CORS_METHODS: [&str; 5] = ["GET", "HEAD", "POST", "PUT", "DELETE"];
+/// Request headers a preflight allows unless configured otherwise, beyond the
+/// CORS-safelisted ones browsers always send.
+pub const DEFAULT_CORS_HEADERS: [&str; 3] = ["Authorization", "Content-Type", "Range"];
+
+/// Lets browsers on `allowed_origin` call the server. Preflight requests are
+/// answered here, before they can reach authentication, with the configured
+/// methods and headers rather than whatever the browser asked for.
+pub struct Cors {
+    pub allowed_origin: String,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+}
+
+impl Middleware for Cors {
+    fn handle(&self, req: &mut Request, next: &dyn Handler) -> Response {
+        let is_preflight = *req.get_method() == HttpMethod::Options
+            && req.get_header("Origin").is_some()
+            && req.get_header("Access-Control-Request-Method").is_some();
+        let mut res = if is_preflight {
+            let mut res = Response::new(Status::NoContent);
+            for (header, allowed) in [
+                ("Access-Control-Allow-Methods", &self.allowed_methods),
+                ("Access-Control-Allow-Headers", &self.allowed_headers),
+            ] {
+                if !allowed.is_empty() {
+                    res.headers.insert(header, allowed.join(", "));
+                }
+            }
+            res
+        } else {
+            next.handle(req)
+        };
+        res.headers
+            .insert("Access-Control-Allow-Origin", self.allowed_origin.clone());
+        if self.allowed_origin != "*" {
+            res.headers.add_to_list("Vary", "Origin");
+        }
+        res
+    }
+}
+
+/// Answers 401 to requests without `Authorization: Bearer <token>`.
+pub struct BearerAuth {
+    pub token: String,
+}
+
+impl Middleware for BearerAuth {
+    fn handle(&self, req: &mut Request, next: &dyn Handler) -> Response {
+        let authorized = req
+            .get_header("Authorization")
+            .and_then(|value| value.split_once(' '))
+            .is_some_and(|(scheme, token)| {
+                scheme.eq_ignore_ascii_case("Bearer")
+                    && constant_time_eq(token.trim().as_bytes(), self.token.as_bytes())
+            });
+        if authorized {
+            return next.handle(req);
+        }
+        let mut res = Response::new(Status::Unauthorized);
+        res.headers.insert("WWW-Authenticate", "Bearer");
+        res
+    }
+}
+
+/// Compares a secret with a guess in a time that depends only on the secret's length,
+/// so timing the answers doesn't tell how much of a guess was right.
+fn constant_time_eq(given: &[u8], expected: &[u8]) -> bool {
+    let mut diff = given.len() ^ expected.len();
+    for (i, &b) in expected.iter().enumerate() {
+        diff |= usize::from(b ^ given.get(i).copied().unwrap_or_default());
+    }
+    diff == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_tokens_whole() {
+        assert!(constant_time_eq(b"s3cret", b"s3cret"));
+        assert!(constant_time_eq(b"", b""));
+        for given in [&b"s3cre"[..], b"s3cretx", b"S3cret", b"", b"s3cret\0"] {
+            assert!(!constant_time_eq(given, b"s3cret"), "{:?}", given);
+        }
+    }
+}
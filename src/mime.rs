@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::http::ContentType;
+
+/// How many leading bytes of a file sniffing and charset detection look at.
+pub const SNIFF_LEN: usize = 512;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Picks the media type of a file served from `/files/` by its extension.
+///
+/// `overrides` maps lowercase extensions, without the dot, ahead of the built-in
+/// table. With `sniff` set, files whose extension is missing or unknown are
+/// recognised by their first bytes instead of being sent as `application/octet-stream`.
+#[derive(Debug, Clone, Default)]
+pub struct MimeTypes {
+    pub overrides: HashMap<String, ContentType>,
+    pub sniff: bool,
+}
+
+impl MimeTypes {
+    /// The media type of the file `name` starting with `head`. Text gets a `charset`
+    /// if it appears to be UTF-8 and the type doesn't name one already.
+    pub fn detect(&self, name: &str, head: &[u8]) -> ContentType {
+        let extension = Path::new(name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        let content_type = extension
+            .and_then(|extension| {
+                self.overrides
+                    .get(&extension)
+                    .cloned()
+                    .or_else(|| from_extension(&extension))
+            })
+            .unwrap_or_else(|| {
+                if self.sniff {
+                    sniff(head)
+                } else {
+                    ContentType::APPLICATION_OCTET_STREAM
+                }
+            });
+        if content_type.is_text() && content_type.get_charset().is_none() && is_utf8(head) {
+            content_type.with_charset("utf-8")
+        } else {
+            content_type
+        }
+    }
+}
+
+pub fn from_extension(extension: &str) -> Option<ContentType> {
+    Some(match extension {
+        "txt" | "text" | "log" => ContentType::TEXT_PLAIN,
+        "html" | "htm" => ContentType::TEXT_HTML,
+        "css" => ContentType::TEXT_CSS,
+        "js" | "mjs" => ContentType::TEXT_JAVASCRIPT,
+        "csv" => ContentType::TEXT_CSV,
+        "md" | "markdown" => ContentType::TEXT_MARKDOWN,
+        "xml" => ContentType::TEXT_XML,
+        "json" => ContentType::APPLICATION_JSON,
+        "wasm" => ContentType::APPLICATION_WASM,
+        "pdf" => ContentType::APPLICATION_PDF,
+        "zip" => ContentType::APPLICATION_ZIP,
+        "gz" | "tgz" => ContentType::APPLICATION_GZIP,
+        "zst" => ContentType::APPLICATION_ZSTD,
+        "xz" => ContentType::APPLICATION_XZ,
+        "bz2" => ContentType::APPLICATION_BZIP2,
+        "7z" => ContentType::APPLICATION_7Z,
+        "png" => ContentType::IMAGE_PNG,
+        "jpg" | "jpeg" => ContentType::IMAGE_JPEG,
+        "gif" => ContentType::IMAGE_GIF,
+        "webp" => ContentType::IMAGE_WEBP,
+        "avif" => ContentType::IMAGE_AVIF,
+        "svg" => ContentType::IMAGE_SVG,
+        "ico" => ContentType::IMAGE_ICON,
+        "woff" => ContentType::FONT_WOFF,
+        "woff2" => ContentType::FONT_WOFF2,
+        "mp3" => ContentType::AUDIO_MPEG,
+        "ogg" => ContentType::AUDIO_OGG,
+        "mp4" => ContentType::VIDEO_MP4,
+        "webm" => ContentType::VIDEO_WEBM,
+        _ => return None,
+    })
+}
+
+/// Recognises common binary formats by their magic numbers and markup by its first
+/// tag. Anything else is plain text unless it holds bytes text never does.
+pub fn sniff(head: &[u8]) -> ContentType {
+    const SIGNATURES: [(&[u8], ContentType); 15] = [
+        (b"\x89PNG\r\n\x1a\n", ContentType::IMAGE_PNG),
+        (b"\xFF\xD8\xFF", ContentType::IMAGE_JPEG),
+        (b"GIF87a", ContentType::IMAGE_GIF),
+        (b"GIF89a", ContentType::IMAGE_GIF),
+        (b"\x00\x00\x01\x00", ContentType::IMAGE_ICON),
+        (b"%PDF-", ContentType::APPLICATION_PDF),
+        (b"PK\x03\x04", ContentType::APPLICATION_ZIP),
+        (b"\x1F\x8B", ContentType::APPLICATION_GZIP),
+        (b"\x28\xB5\x2F\xFD", ContentType::APPLICATION_ZSTD),
+        (b"\xFD7zXZ\x00", ContentType::APPLICATION_XZ),
+        (b"BZh", ContentType::APPLICATION_BZIP2),
+        (b"7z\xBC\xAF\x27\x1C", ContentType::APPLICATION_7Z),
+        (b"\x00asm", ContentType::APPLICATION_WASM),
+        (b"wOFF", ContentType::FONT_WOFF),
+        (b"wOF2", ContentType::FONT_WOFF2),
+    ];
+    if let Some((_, content_type)) = SIGNATURES
+        .into_iter()
+        .find(|(signature, _)| head.starts_with(signature))
+    {
+        return content_type;
+    }
+    if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        return ContentType::IMAGE_WEBP;
+    }
+
+    let text = head
+        .strip_prefix(UTF8_BOM)
+        .unwrap_or(head)
+        .trim_ascii_start();
+    let starts_with = |prefix: &[u8]| {
+        text.get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    };
+    if starts_with(b"<!doctype html") || starts_with(b"<html") {
+        ContentType::TEXT_HTML
+    } else if starts_with(b"<svg")
+        || (starts_with(b"<?xml") && text.windows(4).any(|window| window == b"<svg"))
+    {
+        ContentType::IMAGE_SVG
+    } else if starts_with(b"<?xml") {
+        ContentType::TEXT_XML
+    } else if head.iter().any(|&b| is_binary(b)) {
+        ContentType::APPLICATION_OCTET_STREAM
+    } else {
+        ContentType::TEXT_PLAIN
+    }
+}
+
+/// Control characters other than whitespace and escape, which text doesn't contain.
+fn is_binary(b: u8) -> bool {
+    matches!(b, 0x00..=0x08 | 0x0B | 0x0E..=0x1A | 0x1C..=0x1F)
+}
+
+/// Whether `head` is valid UTF-8, allowing for a character cut off at the end.
+fn is_utf8(head: &[u8]) -> bool {
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    }
+}
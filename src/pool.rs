@@ -0,0 +1,185 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::log_error;
+
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What to do with new work while every worker is busy and the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    /// Block the submitter until there's room in the queue.
+    Wait,
+    /// Hand the work back to the submitter so it can be turned away.
+    Reject,
+}
+
+impl FromStr for OverloadPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wait" => Ok(Self::Wait),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!("expected wait or reject; got {}", s)),
+        }
+    }
+}
+
+/// How many submitted items are waiting for a worker, queued or with their
+/// submitter blocked, and how many were turned away. Handlers get it to tell when
+/// to give up work that can wait, like an idle connection.
+#[derive(Debug, Clone, Default)]
+pub struct Backlog(Arc<BacklogCounts>);
+
+#[derive(Debug, Default)]
+struct BacklogCounts {
+    waiting: AtomicUsize,
+    rejected: AtomicUsize,
+}
+
+impl Backlog {
+    pub fn is_empty(&self) -> bool {
+        self.0.waiting.load(Ordering::SeqCst) == 0
+    }
+
+    /// How many items `OverloadPolicy::Reject` has handed back so far. A change
+    /// means work was turned away in the meantime.
+    pub fn get_rejected(&self) -> usize {
+        self.0.rejected.load(Ordering::SeqCst)
+    }
+
+    fn add(&self) {
+        self.0.waiting.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn remove(&self) {
+        self.0.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn reject(&self) {
+        self.0.waiting.fetch_sub(1, Ordering::SeqCst);
+        self.0.rejected.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// A fixed number of threads running `handler` on items taken from a bounded queue.
+///
+/// Dropping the pool closes the queue and waits for the workers to finish what
+/// was already submitted.
+pub struct ThreadPool<T: Send + 'static> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+    policy: OverloadPolicy,
+    backlog: Backlog,
+}
+
+impl<T: Send + 'static> ThreadPool<T> {
+    pub fn new<F>(size: usize, queue_size: usize, policy: OverloadPolicy, handler: F) -> Self
+    where
+        F: Fn(T, &Backlog) + Send + Sync + 'static,
+    {
+        assert!(size > 0, "A thread pool needs at least one worker.");
+        let (sender, receiver) = mpsc::sync_channel::<T>(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        let backlog = Backlog::default();
+        let workers = (0..size)
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                let handler = Arc::clone(&handler);
+                let backlog = backlog.clone();
+                thread::Builder::new()
+                    .name(format!("worker-{}", id))
+                    .spawn(move || run_worker(&receiver, &backlog, handler.as_ref()))
+                    .expect("Failed to spawn a worker thread.")
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            workers,
+            policy,
+            backlog,
+        }
+    }
+
+    /// Queues `item` for a worker. Under `OverloadPolicy::Reject` a full queue gives
+    /// the item back as `Err` at once; under `OverloadPolicy::Wait` this blocks instead.
+    pub fn submit(&self, item: T) -> Result<(), T> {
+        let sender = self
+            .sender
+            .as_ref()
+            .expect("The queue is open until the pool is dropped.");
+        // The worker taking the item removes it from the backlog.
+        self.backlog.add();
+        let result = match self.policy {
+            OverloadPolicy::Wait => sender.send(item).map_err(|err| err.0),
+            OverloadPolicy::Reject => sender.try_send(item).map_err(|err| match err {
+                TrySendError::Full(item) | TrySendError::Disconnected(item) => item,
+            }),
+        };
+        if result.is_err() {
+            self.backlog.reject();
+        }
+        result
+    }
+
+    /// Closes the queue and gives the workers up to `timeout` to finish what was
+    /// already submitted. Returns whether they all did; workers still busy after
+    /// the deadline are left running detached.
+    pub fn shutdown(mut self, timeout: Duration) -> bool {
+        drop(self.sender.take());
+        let deadline = Instant::now() + timeout;
+        while self.workers.iter().any(|worker| !worker.is_finished()) {
+            if Instant::now() >= deadline {
+                self.workers.clear();
+                return false;
+            }
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        true
+    }
+}
+
+impl<T: Send + 'static> Drop for ThreadPool<T> {
+    fn drop(&mut self) {
+        // Workers stop once the queue is closed and drained.
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run_worker<T, F: Fn(T, &Backlog)>(
+    receiver: &Mutex<Receiver<T>>,
+    backlog: &Backlog,
+    handler: &F,
+) {
+    loop {
+        let item = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let item = match item {
+            Ok(item) => item,
+            Err(_) => return,
+        };
+        backlog.remove();
+        // A panicking job must not take the worker down with it.
+        if panic::catch_unwind(AssertUnwindSafe(|| handler(item, backlog))).is_err() {
+            log_error!(
+                "A job panicked on {}",
+                thread::current().name().unwrap_or("a worker")
+            );
+        }
+    }
+}
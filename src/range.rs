@@ -0,0 +1,390 @@
+use std::collections::hash_map::RandomState;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::time::{Duration, SystemTime};
+
+use crate::http::date;
+use crate::http::headers::HeaderMap;
+use crate::http::response::{Body, Content};
+use crate::http::ContentType;
+
+/// More ranges than this in one request look like an attempt to make the server do
+/// needless work rather than a download resuming, so the whole body is sent instead.
+pub const MAX_RANGES: usize = 32;
+
+/// An inclusive span of byte offsets into a body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn get_length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// The `Content-Range` value for this range of a body `complete_len` bytes long.
+    pub fn to_content_range(&self, complete_len: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, complete_len)
+    }
+}
+
+/// One element of a `Range` header, before it is applied to a length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeSpec {
+    /// `first-last` or `first-`.
+    From { first: u64, last: Option<u64> },
+    /// `-len`, the last `len` bytes.
+    Suffix(u64),
+}
+
+impl RangeSpec {
+    /// The bytes this selects from a body `len` bytes long, if any.
+    fn resolve(&self, len: u64) -> Option<ByteRange> {
+        let (start, end) = match *self {
+            Self::From { first, last } => (first, last.map_or(len - 1, |last| last.min(len - 1))),
+            Self::Suffix(suffix_len) if suffix_len > 0 => (len.saturating_sub(suffix_len), len - 1),
+            Self::Suffix(_) => return None,
+        };
+        (start < len).then_some(ByteRange { start, end })
+    }
+}
+
+/// What to send for a `Range` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeSelection {
+    /// The header doesn't apply, so the whole body goes out as usual.
+    Full,
+    /// The satisfiable ranges, in ascending order with overlapping and adjacent ones
+    /// merged.
+    Partial(Vec<ByteRange>),
+    /// None of the ranges overlaps the body.
+    Unsatisfiable,
+}
+
+/// Applies `Range: bytes=...` to a body `len` bytes long. Other units, malformed
+/// headers and more than `MAX_RANGES` ranges are ignored.
+pub fn select_ranges(range: &str, len: u64) -> RangeSelection {
+    let specs = match parse_range(range) {
+        Some(specs) => specs,
+        None => return RangeSelection::Full,
+    };
+    let mut ranges = if len == 0 {
+        Vec::new()
+    } else {
+        specs
+            .iter()
+            .filter_map(|spec| spec.resolve(len))
+            .collect::<Vec<ByteRange>>()
+    };
+    if ranges.is_empty() {
+        return RangeSelection::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    RangeSelection::Partial(merged)
+}
+
+fn parse_range(range: &str) -> Option<Vec<RangeSpec>> {
+    let (unit, set) = range.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let number = |digits: &str| {
+        (!digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| digits.parse::<u64>().ok())
+            .flatten()
+    };
+    let specs = set
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| {
+            let (first, last) = spec.split_once('-')?;
+            if first.is_empty() {
+                return number(last).map(RangeSpec::Suffix);
+            }
+            let first = number(first)?;
+            let last = match last {
+                "" => None,
+                last => Some(number(last).filter(|last| *last >= first)?),
+            };
+            Some(RangeSpec::From { first, last })
+        })
+        .collect::<Option<Vec<RangeSpec>>>()?;
+    (!specs.is_empty() && specs.len() <= MAX_RANGES).then_some(specs)
+}
+
+/// Whether `If-Range` still describes the response with `headers`, so the ranges
+/// asked for can be sent. An entity tag has to match `ETag` strongly; a date has
+/// to equal a `Last-Modified` that is at least a second old, as a newer one may
+/// not have caught every change.
+pub fn if_range_matches(if_range: &str, headers: &HeaderMap) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') {
+        return headers.get("ETag") == Some(if_range);
+    }
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    let last_modified = headers.get("Last-Modified").and_then(date::parse_http_date);
+    match (date::parse_http_date(if_range), last_modified) {
+        (Some(date), Some(last_modified)) => {
+            date == last_modified
+                && SystemTime::now()
+                    .duration_since(last_modified)
+                    .is_ok_and(|age| age >= Duration::from_secs(1))
+        }
+        _ => false,
+    }
+}
+
+/// Cuts `content` down to `range`. The body has to be of known length.
+pub fn slice_content(content: Content, range: ByteRange) -> io::Result<Content> {
+    let body = match content.body {
+        Body::Bytes(bytes) => {
+            Body::Bytes(bytes[range.start as usize..=range.end as usize].to_vec())
+        }
+        Body::File { mut file, .. } => {
+            file.seek(SeekFrom::Start(range.start))?;
+            Body::File {
+                file,
+                len: range.get_length(),
+            }
+        }
+        Body::Stream(_) => return Err(unsized_body()),
+    };
+    Ok(Content { body, ..content })
+}
+
+/// Puts `ranges` of `content` into a `multipart/byteranges` body, each part with
+/// its own `Content-Type` and `Content-Range`. File parts are read as they are sent.
+pub fn multipart_content(
+    content: Content,
+    ranges: &[ByteRange],
+    complete_len: u64,
+) -> io::Result<Content> {
+    let boundary = make_boundary();
+    let part_head = |i: usize, range: &ByteRange| {
+        format!(
+            "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            if i == 0 { "" } else { "\r\n" },
+            boundary,
+            content.content_type,
+            range.to_content_range(complete_len)
+        )
+    };
+    let closing = format!("\r\n--{}--\r\n", boundary);
+
+    let body = match &content.body {
+        Body::Bytes(bytes) => {
+            let mut multipart = Vec::new();
+            for (i, range) in ranges.iter().enumerate() {
+                multipart.extend_from_slice(part_head(i, range).as_bytes());
+                multipart.extend_from_slice(&bytes[range.start as usize..=range.end as usize]);
+            }
+            multipart.extend_from_slice(closing.as_bytes());
+            Body::Bytes(multipart)
+        }
+        Body::File { file, .. } => {
+            let mut reader: Box<dyn Read + Send> = Box::new(io::empty());
+            for (i, range) in ranges.iter().enumerate() {
+                let section = FileSection {
+                    file: file.try_clone()?,
+                    range: *range,
+                    remaining: None,
+                };
+                reader = Box::new(
+                    reader
+                        .chain(Cursor::new(part_head(i, range)))
+                        .chain(section),
+                );
+            }
+            Body::Stream(Box::new(reader.chain(Cursor::new(closing))))
+        }
+        Body::Stream(_) => return Err(unsized_body()),
+    };
+    Ok(Content {
+        content_type: ContentType::MULTIPART_BYTERANGES.with_param("boundary", boundary),
+        body,
+        encoding: content.encoding,
+    })
+}
+
+/// A range of a file, read from a handle that shares its position with others: it
+/// seeks to the start of the range on the first read.
+struct FileSection {
+    file: File,
+    range: ByteRange,
+    remaining: Option<u64>,
+}
+
+impl Read for FileSection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                self.file.seek(SeekFrom::Start(self.range.start))?;
+                self.range.get_length()
+            }
+        };
+        let max = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        let read = self.file.read(&mut buf[..max])?;
+        if read == 0 && remaining > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("file ended {} bytes before the end of the range", remaining),
+            ));
+        }
+        self.remaining = Some(remaining - read as u64);
+        Ok(read)
+    }
+}
+
+/// A boundary that can't be predicted from outside, so a file can't be crafted to
+/// contain it.
+fn make_boundary() -> String {
+    let random = || RandomState::new().build_hasher().finish();
+    format!("{:016x}{:016x}", random(), random())
+}
+
+fn unsized_body() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "ranges need a body of known length",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(ranges: &[(u64, u64)]) -> RangeSelection {
+        RangeSelection::Partial(
+            ranges
+                .iter()
+                .map(|&(start, end)| ByteRange { start, end })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn selects_single_ranges() {
+        assert_eq!(select_ranges("bytes=0-9", 100), partial(&[(0, 9)]));
+        assert_eq!(select_ranges("bytes=90-", 100), partial(&[(90, 99)]));
+        assert_eq!(select_ranges("bytes=90-500", 100), partial(&[(90, 99)]));
+        assert_eq!(select_ranges("bytes=-10", 100), partial(&[(90, 99)]));
+        assert_eq!(select_ranges("bytes=-500", 100), partial(&[(0, 99)]));
+        assert_eq!(select_ranges(" Bytes = 5-5 ", 100), partial(&[(5, 5)]));
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(
+            select_ranges("bytes=50-59, 0-9, 5-19, 20-29", 100),
+            partial(&[(0, 29), (50, 59)])
+        );
+        assert_eq!(
+            select_ranges("bytes=0-, -10, 40-49", 100),
+            partial(&[(0, 99)])
+        );
+        assert_eq!(
+            select_ranges("bytes=200-300, 10-19", 100),
+            partial(&[(10, 19)])
+        );
+    }
+
+    #[test]
+    fn reports_ranges_outside_the_body() {
+        assert_eq!(
+            select_ranges("bytes=-0", 100),
+            RangeSelection::Unsatisfiable
+        );
+        assert_eq!(
+            select_ranges("bytes=100-", 100),
+            RangeSelection::Unsatisfiable
+        );
+        assert_eq!(
+            select_ranges("bytes=100-199, -0", 100),
+            RangeSelection::Unsatisfiable
+        );
+        assert_eq!(select_ranges("bytes=0-9", 0), RangeSelection::Unsatisfiable);
+        assert_eq!(select_ranges("bytes=-10", 0), RangeSelection::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_headers_it_cannot_use() {
+        for range in [
+            "items=0-9",
+            "bytes 0-9",
+            "bytes=",
+            "bytes=,",
+            "bytes=9-0",
+            "bytes=0-9, x",
+            "bytes=+1-2",
+            "bytes=1--2",
+            "bytes=--1",
+            "bytes=99999999999999999999-",
+        ] {
+            assert_eq!(
+                select_ranges(range, 100),
+                RangeSelection::Full,
+                "{:?}",
+                range
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_too_many_ranges() {
+        let ranges = |count: usize| {
+            let specs = (0..count)
+                .map(|i| format!("{}-{}", i * 2, i * 2))
+                .collect::<Vec<String>>();
+            format!("bytes={}", specs.join(","))
+        };
+        assert!(matches!(
+            select_ranges(&ranges(MAX_RANGES), 100),
+            RangeSelection::Partial(ranges) if ranges.len() == MAX_RANGES
+        ));
+        assert_eq!(
+            select_ranges(&ranges(MAX_RANGES + 1), 100),
+            RangeSelection::Full
+        );
+    }
+
+    #[test]
+    fn matches_if_range_validators() {
+        let mut headers = HeaderMap::new();
+        headers.insert("ETag", "\"abc\"");
+        headers.insert("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(if_range_matches(" \"abc\" ", &headers));
+        assert!(!if_range_matches("\"abd\"", &headers));
+        assert!(!if_range_matches("W/\"abc\"", &headers));
+        assert!(if_range_matches("Sun, 06 Nov 1994 08:49:37 GMT", &headers));
+        assert!(!if_range_matches("Sun, 06 Nov 1994 08:49:38 GMT", &headers));
+        assert!(!if_range_matches("yesterday", &headers));
+
+        let mut weak = HeaderMap::new();
+        weak.insert("ETag", "W/\"abc\"");
+        assert!(!if_range_matches("W/\"abc\"", &weak));
+
+        let mut fresh = HeaderMap::new();
+        let now = date::format_http_date(SystemTime::now() + Duration::from_secs(2));
+        fresh.insert("Last-Modified", now.as_str());
+        assert!(!if_range_matches(&now, &fresh));
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use crate::handler::{Handler, IntoResponse};
+use crate::http::request::Request;
+use crate::http::response::Response;
+use crate::http::uri::{split_path, TargetForm};
+use crate::http::{HttpMethod, Status};
+
+/// One `/`-separated piece of a route pattern.
+#[derive(Debug)]
+enum Segment {
+    /// Matches the segment literally.
+    Static(String),
+    /// `:name` captures exactly one non-empty segment.
+    Param(String),
+    /// `*name` captures the rest of the path, slashes included. Only valid last.
+    Wildcard(String),
+}
+
+struct Route {
+    method: HttpMethod,
+    segments: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+impl Route {
+    fn match_path(&self, path: &[&str]) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(expected) => {
+                    if path.get(i) != Some(&expected.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => match path.get(i) {
+                    Some(value) if !value.is_empty() => {
+                        params.insert(name.clone(), value.to_string());
+                    }
+                    _ => return None,
+                },
+                Segment::Wildcard(name) => {
+                    if i >= path.len() {
+                        return None;
+                    }
+                    params.insert(name.clone(), path[i..].join("/"));
+                    return Some(params);
+                }
+            }
+        }
+        (path.len() == self.segments.len()).then_some(params)
+    }
+}
+
+/// Dispatches requests to handlers registered by method and path pattern, e.g.
+/// `/echo/*text` or `/users/:id`. Captures are available through `Request::get_param`.
+///
+/// HEAD falls back to the GET handler, OPTIONS is answered with the allowed methods
+/// unless registered explicitly, a known path with an unregistered method gets 405
+/// and an extension method gets 501.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for `method` on `pattern`. Routes are tried in the order
+    /// they were added.
+    pub fn route<H>(&mut self, method: HttpMethod, pattern: &str, handler: H) -> &mut Self
+    where
+        H: Handler + 'static,
+    {
+        let segments = split_path(pattern)
+            .into_iter()
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_owned())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Wildcard(name.to_owned())
+                } else {
+                    Segment::Static(segment.to_owned())
+                }
+            })
+            .collect::<Vec<Segment>>();
+        assert!(
+            segments
+                .iter()
+                .rev()
+                .skip(1)
+                .all(|segment| !matches!(segment, Segment::Wildcard(_))),
+            "A wildcard has to be the last segment of {}",
+            pattern
+        );
+        self.routes.push(Route {
+            method,
+            segments,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F, R>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::Get, pattern, handler)
+    }
+
+    pub fn post<F, R>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::Post, pattern, handler)
+    }
+
+    pub fn put<F, R>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::Put, pattern, handler)
+    }
+
+    pub fn delete<F, R>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(HttpMethod::Delete, pattern, handler)
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, req: &mut Request) -> Response {
+        let method = req.get_method().clone();
+        if let HttpMethod::Extension(_) = method {
+            return Response::new(Status::NotImplemented);
+        }
+
+        // `OPTIONS *` asks about the server as a whole.
+        if req.get_target_form() == TargetForm::Asterisk {
+            let allowed_methods = self.routes.iter().map(|route| route.method.clone());
+            return allow_response(Status::NoContent, allowed_methods.collect());
+        }
+
+        let segments = req.get_path_segments().to_vec();
+        let path = segments.iter().map(String::as_str).collect::<Vec<&str>>();
+        let mut allowed_methods: Vec<HttpMethod> = Vec::new();
+        let mut head_fallback = None;
+        for route in &self.routes {
+            let params = match route.match_path(&path) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.method == method {
+                req.set_params(params);
+                return route.handler.handle(req);
+            }
+            if method == HttpMethod::Head && route.method == HttpMethod::Get {
+                head_fallback.get_or_insert((route, params));
+            }
+            allowed_methods.push(route.method.clone());
+        }
+
+        if let Some((route, params)) = head_fallback {
+            req.set_params(params);
+            return route.handler.handle(req);
+        }
+        if allowed_methods.is_empty() {
+            return Response::new(Status::NotFound);
+        }
+
+        let status = if method == HttpMethod::Options {
+            Status::NoContent
+        } else {
+            Status::MethodNotAllowed
+        };
+        allow_response(status, allowed_methods)
+    }
+}
+
+/// `status` with an `Allow` header listing `allowed_methods`, plus the HEAD and
+/// OPTIONS the router answers on their behalf.
+fn allow_response(status: Status, mut allowed_methods: Vec<HttpMethod>) -> Response {
+    if allowed_methods.contains(&HttpMethod::Get) {
+        allowed_methods.push(HttpMethod::Head);
+    }
+    allowed_methods.push(HttpMethod::Options);
+    let mut allow: Vec<&str> = Vec::new();
+    for method in &allowed_methods {
+        if !allow.contains(&method.to_string()) {
+            allow.push(method.to_string());
+        }
+    }
+
+    let mut res = Response::new(status);
+    res.headers.insert("Allow", allow.join(", "));
+    res
+}